@@ -0,0 +1,376 @@
+extern crate proc_macro;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::collections::BTreeMap;
+use syn::{
+    parse_macro_input,
+    Data,
+    DeriveInput,
+    Error,
+    Fields,
+    Ident,
+    LitInt,
+    LitStr,
+    Result,
+    Type,
+    Variant
+};
+
+// #[derive(Instruction)] writes the parts of an instruction set that only
+// depend on how its instructions are spelled:
+//
+//   - fmt::Display and serde::Deserialize, so scripts parse and print
+//   - gsm::Bytecode, numbering the opcodes in order from 0
+//   - gsm::Effect, if any instruction declares its stack effect
+//
+// what the instructions do is still up to a hand written gsm::Instruction.
+// variants take these in #[gsm(...)]:
+//
+//   token = "+"                  how a unit variant is written, its name in
+//                                upper case otherwise
+//   literal                      a one field variant holding a value that
+//                                is written with FromStr and Display
+//   text                         a literal that quoted tokens are read as
+//                                by from_quoted, written in quotes when it
+//                                would otherwise be read as something else
+//   skip                         a variant that is never in a script, such
+//                                as an open file handle
+//   opcode = 16                  the opcode for the variant, the ones after
+//                                it carry on from there
+//   effect(pops = 2, pushes = 1) what the variant does to the stack
+#[proc_macro_derive(Instruction, attributes(gsm))]
+pub fn derive_instruction(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(ts) => ts.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+enum Kind {
+    Token(String),
+    // text literals are the ones quoted tokens go to
+    Literal(Box<Type>, bool),
+    Skip
+}
+
+struct Instr {
+    ident: Ident,
+    kind: Kind,
+    opcode: u8,
+    effect: Option<(usize, usize)>
+}
+
+#[derive(Default)]
+struct Attrs {
+    token: Option<LitStr>,
+    literal: bool,
+    text: bool,
+    skip: bool,
+    opcode: Option<LitInt>,
+    effect: Option<(usize, usize)>
+}
+
+fn attrs(v: &Variant) -> Result<Attrs> {
+    let mut a = Attrs::default();
+    for attr in v.attrs.iter().filter(|a| a.path().is_ident("gsm")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("token") {
+                a.token = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("literal") {
+                a.literal = true;
+            } else if meta.path.is_ident("text") {
+                a.text = true;
+            } else if meta.path.is_ident("skip") {
+                a.skip = true;
+            } else if meta.path.is_ident("opcode") {
+                a.opcode = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("effect") {
+                let (mut pops, mut pushes) = (0, 0);
+                meta.parse_nested_meta(|m| {
+                    let n: LitInt = m.value()?.parse()?;
+                    if m.path.is_ident("pops") {
+                        pops = n.base10_parse()?;
+                    } else if m.path.is_ident("pushes") {
+                        pushes = n.base10_parse()?;
+                    } else {
+                        return Err(m.error("expected pops or pushes"));
+                    }
+                    Ok(())
+                })?;
+                a.effect = Some((pops, pushes));
+            } else {
+                return Err(meta.error("expected token, literal, text, skip, opcode or effect"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(a)
+}
+
+fn instrs(input: &DeriveInput) -> Result<Vec<Instr>> {
+    let data = match &input.data {
+        Data::Enum(d) => d,
+        _ => return Err(Error::new_spanned(&input.ident, "Instruction can only be derived for enums"))
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(&input.generics, "Instruction can't be derived for generic enums"));
+    }
+
+    let mut out: Vec<Instr> = Vec::new();
+    let mut tokens: BTreeMap<String, Ident> = BTreeMap::new();
+    let mut opcodes: BTreeMap<u8, Ident> = BTreeMap::new();
+    let mut next: u16 = 0;
+    let mut text: Option<Ident> = None;
+    for v in &data.variants {
+        let a = attrs(v)?;
+        if a.text {
+            if let Some(other) = text.replace(v.ident.clone()) {
+                return Err(Error::new_spanned(v, format!("{} is already the text variant", other)));
+            }
+        }
+        let kind = match (&v.fields, a.literal || a.text, a.skip) {
+            (_, true, true) => return Err(Error::new_spanned(v, "a variant can't be both literal and skip")),
+            (_, _, true) => Kind::Skip,
+            (Fields::Unnamed(f), true, _) if f.unnamed.len() == 1 => Kind::Literal(Box::new(f.unnamed[0].ty.clone()), a.text),
+            (_, true, _) => return Err(Error::new_spanned(v, "a literal must have exactly one unnamed field")),
+            (Fields::Unit, _, _) => {
+                let t = match &a.token {
+                    Some(t) => t.value(),
+                    None => v.ident.to_string().to_uppercase()
+                };
+                if t.is_empty() {
+                    return Err(Error::new_spanned(v, "a token can't be empty"));
+                }
+                if let Some(other) = tokens.insert(t.clone(), v.ident.clone()) {
+                    return Err(Error::new_spanned(v, format!("token '{}' is already used by {}", t, other)));
+                }
+                Kind::Token(t)
+            },
+            _ => return Err(Error::new_spanned(v, "a variant with fields must be #[gsm(literal)] or #[gsm(skip)]"))
+        };
+        if a.token.is_some() && !matches!(kind, Kind::Token(_)) {
+            return Err(Error::new_spanned(v, "only unit variants have a token"));
+        }
+
+        if let Some(n) = &a.opcode {
+            next = u16::from(n.base10_parse::<u8>()?);
+        }
+        if next > u16::from(u8::MAX) {
+            return Err(Error::new_spanned(v, "out of opcodes, there can only be 256"));
+        }
+        let opcode = next as u8;
+        if let Some(other) = opcodes.insert(opcode, v.ident.clone()) {
+            return Err(Error::new_spanned(v, format!("opcode {} is already used by {}", opcode, other)));
+        }
+        next += 1;
+
+        out.push(Instr { ident: v.ident.clone(), kind, opcode, effect: a.effect });
+    }
+
+    // once one instruction says what it does to the stack they all must,
+    // apart from values, which push themselves
+    if out.iter().any(|i| i.effect.is_some()) {
+        for i in out.iter_mut() {
+            match (&i.kind, i.effect) {
+                (_, Some(_)) => {},
+                (Kind::Token(_), None) => return Err(Error::new_spanned(&i.ident, "every instruction needs #[gsm(effect(...))] once one has it")),
+                (_, None) => i.effect = Some((0, 1))
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream> {
+    let name = &input.ident;
+    let instrs = instrs(input)?;
+    let expecting = format!("{} token", name);
+
+    let display = instrs.iter().map(|i| {
+        let v = &i.ident;
+        match &i.kind {
+            Kind::Token(t) => quote! { #name::#v => f.write_str(#t) },
+            Kind::Literal(_, false) => quote! { #name::#v(x) => ::std::fmt::Display::fmt(x, f) },
+            Kind::Literal(_, true) => quote! {
+                #name::#v(x) => {
+                    let s = ::std::string::ToString::to_string(x);
+                    let bare = ::gsm::literal::bare(&s) && ::std::matches!(
+                        ::gsm::__serde::de::Visitor::visit_str::<::gsm::__serde::de::value::Error>(__Visitor, &s),
+                        ::std::result::Result::Ok(#name::#v(_))
+                    );
+                    if bare { f.write_str(&s) } else { f.write_str(&::gsm::literal::quote(&s)) }
+                }
+            },
+            Kind::Skip => {
+                let s = format!("<{}>", v);
+                quote! { #name::#v { .. } => f.write_str(#s) }
+            }
+        }
+    });
+
+    let tokens = instrs.iter().filter_map(|i| {
+        let v = &i.ident;
+        match &i.kind {
+            Kind::Token(t) => Some(quote! { #t => return ::std::result::Result::Ok(#name::#v), }),
+            _ => None
+        }
+    });
+    // values are tried in the order they are declared
+    let literals = instrs.iter().filter_map(|i| {
+        let v = &i.ident;
+        match &i.kind {
+            Kind::Literal(ty, _) => Some(quote! {
+                if let ::std::result::Result::Ok(x) = <#ty as ::std::str::FromStr>::from_str(v) {
+                    return ::std::result::Result::Ok(#name::#v(x));
+                }
+            }),
+            _ => None
+        }
+    });
+
+    // quoted tokens are only ever text, the Instruction impl opts in to this
+    // by returning from_quoted from Instruction::quoted
+    let quoted = match instrs.iter().find(|i| matches!(i.kind, Kind::Literal(_, true))) {
+        Some(Instr { ident: v, kind: Kind::Literal(ty, _), .. }) => quote! {
+            <#ty as ::std::str::FromStr>::from_str(text).ok().map(#name::#v)
+        },
+        _ => quote! { ::std::option::Option::None }
+    };
+
+    let opcodes = instrs.iter().map(|i| {
+        let (v, op) = (&i.ident, i.opcode);
+        quote! { #name::#v { .. } => #op }
+    });
+    let encode = instrs.iter().filter_map(|i| {
+        let v = &i.ident;
+        match &i.kind {
+            Kind::Literal(..) => Some(quote! { #name::#v(x) => e.str(&x.to_string()), }),
+            _ => None
+        }
+    });
+    let decode = instrs.iter().filter_map(|i| {
+        let (v, op) = (&i.ident, i.opcode);
+        match &i.kind {
+            Kind::Token(_) => Some(quote! { #op => ::std::result::Result::Ok(#name::#v), }),
+            Kind::Literal(ty, _) => Some(quote! {
+                #op => {
+                    let s = d.str()?;
+                    <#ty as ::std::str::FromStr>::from_str(s)
+                        .map(#name::#v)
+                        .map_err(|_| d.invalid("bad operand"))
+                },
+            }),
+            Kind::Skip => None
+        }
+    });
+
+    let effect = if instrs.iter().any(|i| i.effect.is_some()) {
+        let arms = instrs.iter().map(|i| {
+            let v = &i.ident;
+            let (pops, pushes) = i.effect.unwrap_or((0, 0));
+            quote! { #name::#v { .. } => ::gsm::StackEffect::new(#pops, #pushes) }
+        });
+        quote! {
+            impl ::gsm::Effect for #name {
+                fn effect(&self) -> ::gsm::StackEffect {
+                    match self {
+                        #(#arms,)*
+                    }
+                }
+            }
+        }
+    } else {
+        TokenStream::new()
+    };
+
+    Ok(quote! {
+        // the visitor is kept out of the namespace of the enum
+        const _: () = {
+            struct __Visitor;
+
+            impl ::std::fmt::Display for #name {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                    match self {
+                        #(#display,)*
+                    }
+                }
+            }
+
+            impl<'de> ::gsm::__serde::de::Visitor<'de> for __Visitor {
+                type Value = #name;
+
+                fn expecting(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                    f.write_str(#expecting)
+                }
+
+                // a literal that can't fail to parse makes the rest unreachable
+                #[allow(unreachable_code, irrefutable_let_patterns)]
+                fn visit_str<E: ::gsm::__serde::de::Error>(self, v: &str) -> ::std::result::Result<#name, E> {
+                    match v {
+                        #(#tokens)*
+                        _ => {}
+                    }
+                    #(#literals)*
+                    ::std::result::Result::Err(E::custom(::std::format!("unknown token '{}'", v)))
+                }
+
+                // sequences of values in formats like JSON needn't quote them
+                fn visit_bool<E: ::gsm::__serde::de::Error>(self, v: bool) -> ::std::result::Result<#name, E> {
+                    self.visit_str(&v.to_string())
+                }
+
+                fn visit_i64<E: ::gsm::__serde::de::Error>(self, v: i64) -> ::std::result::Result<#name, E> {
+                    self.visit_str(&v.to_string())
+                }
+
+                fn visit_u64<E: ::gsm::__serde::de::Error>(self, v: u64) -> ::std::result::Result<#name, E> {
+                    self.visit_str(&v.to_string())
+                }
+
+                fn visit_f64<E: ::gsm::__serde::de::Error>(self, v: f64) -> ::std::result::Result<#name, E> {
+                    self.visit_str(&v.to_string())
+                }
+            }
+
+            impl<'de> ::gsm::__serde::Deserialize<'de> for #name {
+                fn deserialize<D: ::gsm::__serde::Deserializer<'de>>(d: D) -> ::std::result::Result<#name, D::Error> {
+                    d.deserialize_any(__Visitor)
+                }
+            }
+        };
+
+        impl #name {
+            #[allow(dead_code, unused_variables)]
+            pub fn from_quoted(text: &str) -> ::std::option::Option<#name> {
+                #quoted
+            }
+        }
+
+        impl ::gsm::Bytecode for #name {
+            fn opcode(&self) -> u8 {
+                match self {
+                    #(#opcodes,)*
+                }
+            }
+
+            #[allow(unused_variables)]
+            fn encode(&self, e: &mut ::gsm::Encoder) {
+                match self {
+                    #(#encode)*
+                    _ => {}
+                }
+            }
+
+            fn decode(opcode: u8, d: &mut ::gsm::Decoder) -> ::std::result::Result<#name, ::gsm::BytecodeError> {
+                match opcode {
+                    #(#decode)*
+                    _ => ::std::result::Result::Err(d.unknown(opcode))
+                }
+            }
+        }
+
+        #effect
+    })
+}
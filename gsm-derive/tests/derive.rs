@@ -0,0 +1,172 @@
+extern crate gsm;
+use gsm::{
+    effect,
+    AppIO,
+    Bytecode,
+    Cause,
+    Effect,
+    Instruction,
+    Machine,
+    MachineError,
+    Script,
+    StackEffect
+};
+use std::{
+    io,
+    rc::Rc
+};
+
+#[derive(Clone, Debug, PartialEq, Instruction)]
+enum Instr {
+    #[gsm(literal, effect(pushes = 1))]
+    Num(i64),
+    #[gsm(literal)]
+    Bool(bool),
+    // anything else is a word, so it goes last, and so is anything quoted
+    #[gsm(text)]
+    Word(String),
+    #[gsm(skip)]
+    Handle(Rc<String>),
+
+    #[gsm(token = "+", opcode = 0x10, effect(pops = 2, pushes = 1))]
+    Add,
+    #[gsm(token = "-", effect(pops = 2, pushes = 1))]
+    Sub,
+    #[gsm(effect(pops = 1, pushes = 2))]
+    Dup,
+    #[gsm(token = "2DROP", effect(pops = 2))]
+    TwoDrop
+}
+
+struct NullIO;
+
+impl AppIO<Instr> for NullIO {
+    fn open(&self, _m: &mut Machine<Instr>) -> io::Result<()> { Ok(()) }
+    fn read(&self, _m: &mut Machine<Instr>) -> io::Result<()> { Ok(()) }
+    fn write(&self, _m: &mut Machine<Instr>) -> io::Result<()> { Ok(()) }
+    fn seek(&self, _m: &mut Machine<Instr>) -> io::Result<()> { Ok(()) }
+    fn close(&self, _m: &mut Machine<Instr>) -> io::Result<()> { Ok(()) }
+}
+
+impl Instruction<Instr> for Instr {
+    fn execute(&self, ip: usize, m: &mut Machine<Instr>, _io: &dyn AppIO<Instr>) -> Result<(), MachineError<Instr>> {
+        match self {
+            Instr::Add | Instr::Sub => match (m.pop(), m.pop()) {
+                (Some(Instr::Num(r)), Some(Instr::Num(l))) => {
+                    m.push(Instr::Num(if *self == Instr::Add { l + r } else { l - r }))
+                },
+                _ => return Err(Cause::TypeMismatch.into())
+            },
+            Instr::Dup => match m.pop() {
+                Some(i) => {
+                    m.push(i.clone());
+                    m.push(i);
+                },
+                None => return Err(Cause::StackUnderflow.into())
+            },
+            Instr::TwoDrop => {
+                m.pop();
+                m.pop();
+            },
+            _ => m.push(self.clone())
+        }
+        m.next(ip);
+        Ok(())
+    }
+
+    fn quoted(text: &str) -> Option<Instr> {
+        Instr::from_quoted(text)
+    }
+}
+
+#[test]
+fn derive_parse_print() {
+    let s = "1 true x + - DUP 2DROP -5 \"two words\"";
+    let script = Script::<Instr>::parse(s).unwrap();
+    assert_eq!(script.get(0), Some(Instr::Num(1)));
+    assert_eq!(script.get(1), Some(Instr::Bool(true)));
+    assert_eq!(script.get(2), Some(Instr::Word("x".to_string())));
+    assert_eq!(script.get(3), Some(Instr::Add));
+    assert_eq!(script.get(4), Some(Instr::Sub));
+    assert_eq!(script.get(5), Some(Instr::Dup));
+    assert_eq!(script.get(6), Some(Instr::TwoDrop));
+    assert_eq!(script.get(7), Some(Instr::Num(-5)));
+    assert_eq!(script.get(8), Some(Instr::Word("two words".to_string())));
+    assert_eq!(script.to_string(), s);
+
+    assert_eq!(Instr::Handle(Rc::new("f".to_string())).to_string(), "<Handle>");
+    assert_eq!(Script::<Instr>::parse(&script.to_string()).unwrap().to_string(), s);
+
+    // words that would be read as something else are quoted
+    let words = Script::from(["+", "1", "true", "DUP", "w"].iter().map(|w| Instr::Word(w.to_string())).collect::<Vec<_>>());
+    assert_eq!(words.to_string(), r#""+" "1" "true" "DUP" w"#);
+    assert_eq!(Script::<Instr>::parse(&words.to_string()).unwrap(), words);
+
+    // json numbers and booleans needn't be quoted
+    let json = r#"[2, 3, "+", false]"#;
+    let script: Script<Instr> = gsm::script::seq::deserialize(&mut serde_json::Deserializer::from_str(json)).unwrap();
+    assert_eq!(script.to_string(), "2 3 + false");
+}
+
+#[test]
+fn derive_opcodes() {
+    assert_eq!(Instr::Num(0).opcode(), 0);
+    assert_eq!(Instr::Bool(false).opcode(), 1);
+    assert_eq!(Instr::Word(String::new()).opcode(), 2);
+    assert_eq!(Instr::Handle(Rc::new(String::new())).opcode(), 3);
+    assert_eq!(Instr::Add.opcode(), 0x10);
+    assert_eq!(Instr::Sub.opcode(), 0x11);
+    assert_eq!(Instr::TwoDrop.opcode(), 0x13);
+
+    let script = Script::<Instr>::parse(":top 7 -8 false \"a b\" + 2DROP").unwrap();
+    let back = Script::<Instr>::from_bytecode(&script.to_bytecode()).unwrap();
+    assert_eq!(back.to_string(), script.to_string());
+
+    // open handles can't be stored
+    let handle = Script::from(vec![Instr::Handle(Rc::new(String::new()))]);
+    assert!(Script::<Instr>::from_bytecode(&handle.to_bytecode()).is_err());
+}
+
+#[test]
+fn derive_effects() {
+    assert_eq!(Instr::Num(1).effect(), StackEffect::new(0, 1));
+    assert_eq!(Instr::Word(String::new()).effect(), StackEffect::new(0, 1));
+    assert_eq!(Instr::Add.effect(), StackEffect::new(2, 1));
+    assert_eq!(Instr::TwoDrop.effect(), StackEffect::new(2, 0));
+
+    let script = Script::<Instr>::parse("1 2 DUP + -").unwrap();
+    assert_eq!(effect::analyze(&script).unwrap().max_depth, 3);
+    let mut m = Machine::from(script);
+    assert_eq!(m.execute(&NullIO).unwrap().top(), Some(&Instr::Num(-3)));
+}
+
+// without any effects declared there is no Effect impl to get wrong
+#[derive(Clone, Debug, PartialEq, Instruction)]
+enum Plain {
+    #[gsm(literal)]
+    Num(u8),
+    Halt
+}
+
+impl Instruction<Plain> for Plain {
+    fn execute(&self, ip: usize, m: &mut Machine<Plain>, _io: &dyn AppIO<Plain>) -> Result<(), MachineError<Plain>> {
+        match self {
+            Plain::Num(_) => m.push(self.clone()),
+            Plain::Halt => m.halt()
+        }
+        m.next(ip);
+        Ok(())
+    }
+}
+
+#[test]
+fn derive_plain() {
+    assert_eq!(Script::<Plain>::parse("7 HALT").unwrap().to_string(), "7 HALT");
+    let e = Script::<Plain>::parse("7 halt").unwrap_err();
+    assert_eq!(e.index, Some(1));
+    assert!(e.msg.contains("unknown token 'halt'"));
+
+    // there is no text for quoted tokens to be so they are read as they are
+    assert_eq!(Plain::from_quoted("7"), None);
+    assert_eq!(Script::<Plain>::parse("\"7\" \"HALT\"").unwrap().to_string(), "7 HALT");
+}
@@ -56,7 +56,7 @@ impl Display for Whence {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
 pub struct Mode {
     s: String,
     pub read: bool,
@@ -93,19 +93,6 @@ impl FromStr for Mode {
     }
 }
 
-impl Default for Mode {
-    fn default() -> Self {
-        Mode {
-            s: String::default(),
-            read: false,
-            write: false,
-            append: false,
-            plus: false,
-            binary: false
-        }
-    }
-}
-
 pub struct ModeVisitor;
 
 impl<'de> de::Visitor<'de> for ModeVisitor {
@@ -156,5 +143,11 @@ pub trait AppIO<I: Clone> {
     fn write(&self, m: &mut Machine<I>) -> io::Result<()>;
     fn seek(&self, m: &mut Machine<I>) -> io::Result<()>;
     fn close(&self, m: &mut Machine<I>) -> io::Result<()>;
+
+    // writes out the value on top of the stack for instruction sets that can
+    // print; an AppIO with nowhere to print to leaves this out
+    fn print(&self, _m: &mut Machine<I>) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "nowhere to print"))
+    }
 }
 
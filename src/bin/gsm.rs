@@ -0,0 +1,174 @@
+// runs and inspects scripts written for the reference instruction set
+extern crate gsm;
+use gsm::{
+    reference::{
+        FileIO,
+        Instr
+    },
+    Disassembly,
+    MachineBuilder,
+    Repl,
+    Script,
+    Verifier
+};
+use std::{
+    env,
+    fs,
+    io::{
+        self,
+        Read
+    },
+    process
+};
+
+const USAGE: &str = "usage: gsm <command> [options] [FILE]
+
+Reads the script from FILE, or from stdin if FILE is - or missing.
+
+commands:
+    parse               check that the script parses
+    verify              check the structure of the script
+        --max-len N     allow at most N instructions
+    disasm              list the script with indices and jump notes
+    fmt                 print the script one instruction per line
+    run                 run the script and print the stack, top first
+        --fuel N        stop after N instructions
+    repl                run lines from stdin against one stack, after
+                        running FILE if one is given
+        --fuel N        stop after N instructions in all
+    help                print this message";
+
+// a bad command line, exits with 2
+struct Usage(String);
+
+struct Args {
+    command: String,
+    file: Option<String>,
+    max_len: Option<usize>,
+    fuel: Option<u64>
+}
+
+fn number<T: std::str::FromStr>(flag: &str, v: Option<String>) -> Result<T, Usage> {
+    match v {
+        Some(v) => v.parse().map_err(|_| Usage(format!("{} wants a number, not '{}'", flag, v))),
+        None => Err(Usage(format!("{} wants a number", flag)))
+    }
+}
+
+fn args(mut it: impl Iterator<Item = String>) -> Result<Args, Usage> {
+    let command = match it.next() {
+        Some(c) => c,
+        None => return Err(Usage("no command given".to_string()))
+    };
+    let mut a = Args { command, file: None, max_len: None, fuel: None };
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--max-len" if a.command == "verify" => a.max_len = Some(number("--max-len", it.next())?),
+            "--fuel" if a.command == "run" || a.command == "repl" => a.fuel = Some(number("--fuel", it.next())?),
+            s if s.starts_with("--") => return Err(Usage(format!("unknown option '{}'", s))),
+            _ if a.file.is_some() => return Err(Usage(format!("unexpected argument '{}'", arg))),
+            _ => a.file = Some(arg)
+        }
+    }
+    Ok(a)
+}
+
+fn read(file: &Option<String>) -> Result<(String, String), String> {
+    match file.as_deref() {
+        None | Some("-") => {
+            let mut s = String::new();
+            io::stdin().read_to_string(&mut s).map_err(|e| format!("<stdin>: {}", e))?;
+            Ok(("<stdin>".to_string(), s))
+        },
+        Some(path) => {
+            let s = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            Ok((path.to_string(), s))
+        }
+    }
+}
+
+fn repl(a: &Args) -> Result<String, String> {
+    let mut b = MachineBuilder::new();
+    if let Some(f) = a.fuel {
+        b.fuel(f);
+    }
+    let mut r = Repl::<Instr>::new(b.build());
+    let fio = FileIO::with_output(io::stdout());
+    if let Some(path) = &a.file {
+        r.load(path, &fio).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    let stdin = io::stdin();
+    r.interact(stdin.lock(), io::stdout(), &fio).map_err(|e| e.to_string())?;
+    Ok(String::new())
+}
+
+// what to print on success or on failure
+fn run(a: &Args) -> Result<String, String> {
+    if a.command == "repl" {
+        return repl(a);
+    }
+    let (name, text) = read(&a.file)?;
+    let script = match Script::<Instr>::parse_all(&text) {
+        Ok(s) => s,
+        Err(errs) => {
+            let lines: Vec<String> = errs.iter().map(|e| format!("{}: {}", name, e)).collect();
+            return Err(lines.join("\n"));
+        }
+    };
+
+    match a.command.as_str() {
+        "parse" => Ok(format!("{}: {} instructions, {} labels", name, script.len(), script.labels().len())),
+        "verify" => {
+            let mut v = Verifier::new();
+            if let Some(n) = a.max_len {
+                v.max_len(n);
+            }
+            v.verify(&script).map_err(|e| format!("{}: {}", name, e))?;
+            Ok(format!("{}: ok", name))
+        },
+        "disasm" => Ok(Disassembly::new(&script).to_string().trim_end().to_string()),
+        "fmt" => Ok(format!("{:#}", script).trim_end().to_string()),
+        "run" => {
+            let mut b = MachineBuilder::new();
+            b.script(&script);
+            if let Some(f) = a.fuel {
+                b.fuel(f);
+            }
+            let stack = b.build().execute(&FileIO::with_output(io::stdout())).map_err(|e| format!("{}: {}", name, e))?;
+            Ok(stack.to_string().trim_end().to_string())
+        },
+        c => unreachable!("unchecked command '{}'", c)
+    }
+}
+
+fn main() {
+    let a = match args(env::args().skip(1)) {
+        Ok(a) => a,
+        Err(Usage(msg)) => {
+            eprintln!("gsm: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+    match a.command.as_str() {
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            return;
+        },
+        "parse" | "verify" | "disasm" | "fmt" | "run" | "repl" => {},
+        c => {
+            eprintln!("gsm: unknown command '{}'\n\n{}", c, USAGE);
+            process::exit(2);
+        }
+    }
+    match run(&a) {
+        Ok(out) => {
+            if !out.is_empty() {
+                println!("{}", out);
+            }
+        },
+        Err(msg) => {
+            eprintln!("{}", msg);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::Instruction;
+use std::{
+    clone::Clone,
+    error,
+    fmt
+};
+
+// the part an instruction plays in a structured block such as IF/ELSE/FI
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Block {
+    Open,
+    Split,
+    Close
+}
+
+// where the instructions of a block are, shared by all of them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockInfo {
+    pub open: usize,
+    pub split: Option<usize>,
+    pub close: usize
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockError {
+    Unclosed(usize),
+    Unopened(usize),
+    DuplicateSplit(usize)
+}
+
+impl BlockError {
+    pub fn index(&self) -> usize {
+        match self {
+            BlockError::Unclosed(i) |
+            BlockError::Unopened(i) |
+            BlockError::DuplicateSplit(i) => *i
+        }
+    }
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockError::Unclosed(i) => write!(f, "block opened at {} is never closed", i),
+            BlockError::Unopened(i) => write!(f, "instruction at {} is not inside a block", i),
+            BlockError::DuplicateSplit(i) => write!(f, "block split a second time at {}", i)
+        }
+    }
+}
+
+impl error::Error for BlockError {}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockTable {
+    entries: Vec<Option<BlockInfo>>,
+    depth: Vec<usize>
+}
+
+impl BlockTable {
+    pub fn build<I: Clone + Instruction<I>>(code: &[I]) -> Result<Self, BlockError> {
+        let mut entries = vec![None; code.len()];
+        let mut depth = vec![0; code.len()];
+
+        // the open index and split index, if any, of the enclosing blocks
+        let mut open: Vec<(usize, Option<usize>)> = Vec::new();
+
+        for (ip, i) in code.iter().enumerate() {
+            match i.block() {
+                Some(Block::Open) => {
+                    depth[ip] = open.len();
+                    open.push((ip, None));
+                },
+                Some(Block::Split) => {
+                    match open.last_mut() {
+                        Some((_, split @ None)) => *split = Some(ip),
+                        Some(_) => return Err(BlockError::DuplicateSplit(ip)),
+                        None => return Err(BlockError::Unopened(ip))
+                    }
+                    depth[ip] = open.len() - 1;
+                },
+                Some(Block::Close) => {
+                    let (o, split) = match open.pop() {
+                        Some(o) => o,
+                        None => return Err(BlockError::Unopened(ip))
+                    };
+                    let info = BlockInfo { open: o, split, close: ip };
+                    entries[o] = Some(info);
+                    if let Some(s) = split {
+                        entries[s] = Some(info);
+                    }
+                    entries[ip] = Some(info);
+                    depth[ip] = open.len();
+                },
+                None => depth[ip] = open.len()
+            }
+        }
+
+        match open.pop() {
+            Some((o, _)) => Err(BlockError::Unclosed(o)),
+            None => Ok(BlockTable { entries, depth })
+        }
+    }
+
+    // the block that the instruction at ip opens, splits or closes
+    pub fn get(&self, ip: usize) -> Option<BlockInfo> {
+        self.entries.get(ip).copied().flatten()
+    }
+
+    // the number of blocks around the instruction at ip, not counting the one
+    // it opens, splits or closes
+    pub fn depth(&self, ip: usize) -> usize {
+        self.depth.get(ip).copied().unwrap_or(0)
+    }
+}
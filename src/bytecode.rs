@@ -0,0 +1,190 @@
+use crate::BlockError;
+use std::{
+    error,
+    fmt,
+    str
+};
+
+// the first bytes of every bytecode script and the format version after them
+pub const MAGIC: [u8; 4] = *b"GSMB";
+pub const VERSION: u8 = 1;
+
+// instruction sets that can be stored as bytecode give every instruction an
+// opcode and write any operands after it
+pub trait Bytecode: Sized {
+    fn opcode(&self) -> u8;
+    fn encode(&self, _e: &mut Encoder) {}
+    fn decode(opcode: u8, d: &mut Decoder) -> Result<Self, BytecodeError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BytecodeError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated(usize),
+    VarintOverflow(usize),
+    InvalidUtf8(usize),
+    UnknownOpcode { offset: usize, opcode: u8 },
+    InvalidOperand { offset: usize, msg: String },
+    BadLabel(String),
+    TrailingBytes(usize),
+    Block(BlockError)
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::BadMagic => write!(f, "not a bytecode script"),
+            BytecodeError::UnsupportedVersion(v) => write!(f, "unsupported bytecode version {}", v),
+            BytecodeError::Truncated(o) => write!(f, "bytecode ends unexpectedly at byte {}", o),
+            BytecodeError::VarintOverflow(o) => write!(f, "varint too large at byte {}", o),
+            BytecodeError::InvalidUtf8(o) => write!(f, "invalid utf-8 at byte {}", o),
+            BytecodeError::UnknownOpcode { offset, opcode } => write!(f, "unknown opcode {:#04x} at byte {}", opcode, offset),
+            BytecodeError::InvalidOperand { offset, msg } => write!(f, "{} at byte {}", msg, offset),
+            BytecodeError::BadLabel(l) => write!(f, "bad label '{}'", l),
+            BytecodeError::TrailingBytes(o) => write!(f, "unexpected bytes after the script at byte {}", o),
+            BytecodeError::Block(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl error::Error for BytecodeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BytecodeError::Block(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+// operands are written as LEB128 varints, zigzag for signed values, and
+// length prefixed bytes
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    pub fn signed(&mut self, v: i64) {
+        self.varint(((v << 1) ^ (v >> 63)) as u64);
+    }
+
+    pub fn bytes(&mut self, b: &[u8]) {
+        self.varint(b.len() as u64);
+        self.buf.extend_from_slice(b);
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Decoder { buf, pos: 0 }
+    }
+
+    // the position of the next byte to be read
+    pub fn offset(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn u8(&mut self) -> Result<u8, BytecodeError> {
+        match self.buf.get(self.pos) {
+            Some(b) => {
+                self.pos += 1;
+                Ok(*b)
+            },
+            None => Err(BytecodeError::Truncated(self.pos))
+        }
+    }
+
+    pub fn varint(&mut self) -> Result<u64, BytecodeError> {
+        let start = self.pos;
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            // the tenth byte may only hold the top bit of a u64
+            if shift == 63 && b > 1 {
+                return Err(BytecodeError::VarintOverflow(start));
+            }
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(BytecodeError::VarintOverflow(start))
+    }
+
+    pub fn signed(&mut self) -> Result<i64, BytecodeError> {
+        let v = self.varint()?;
+        Ok(((v >> 1) as i64) ^ -((v & 1) as i64))
+    }
+
+    // a varint that must fit in a usize and be no more than max
+    pub fn len(&mut self, max: usize) -> Result<usize, BytecodeError> {
+        let start = self.pos;
+        let v = self.varint()?;
+        if v > max as u64 {
+            return Err(BytecodeError::Truncated(start));
+        }
+        Ok(v as usize)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], BytecodeError> {
+        let start = self.pos;
+        let n = self.varint()?;
+        if n > self.remaining() as u64 {
+            return Err(BytecodeError::Truncated(start));
+        }
+        let n = n as usize;
+        let b = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(b)
+    }
+
+    pub fn str(&mut self) -> Result<&'a str, BytecodeError> {
+        let start = self.pos;
+        let b = self.bytes()?;
+        str::from_utf8(b).map_err(|_| BytecodeError::InvalidUtf8(start))
+    }
+
+    // for Bytecode::decode to report an opcode it doesn't know
+    pub fn unknown(&self, opcode: u8) -> BytecodeError {
+        BytecodeError::UnknownOpcode { offset: self.pos.saturating_sub(1), opcode }
+    }
+
+    pub fn invalid(&self, msg: &str) -> BytecodeError {
+        BytecodeError::InvalidOperand { offset: self.pos, msg: msg.to_string() }
+    }
+}
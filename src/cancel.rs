@@ -0,0 +1,30 @@
+use std::sync::{
+    atomic::{
+        AtomicBool,
+        Ordering
+    },
+    Arc
+};
+
+// a handle that stops a machine at the next instruction boundary, it can be
+// cloned and sent to other threads and stays cancelled until it is reset
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken(Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
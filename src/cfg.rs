@@ -0,0 +1,157 @@
+use crate::{
+    Flow,
+    Instruction,
+    Script
+};
+use std::{
+    collections::BTreeSet,
+    fmt
+};
+
+// a run of instructions from start up to end that is only ever entered at
+// the start and only ever left at the end
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeKind {
+    // falling through to the next instruction
+    Next,
+    Jump,
+    Branch,
+    Call,
+    // from a call to where it comes back to
+    Return,
+    Halt
+}
+
+impl fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EdgeKind::Next => write!(f, "next"),
+            EdgeKind::Jump => write!(f, "jump"),
+            EdgeKind::Branch => write!(f, "branch"),
+            EdgeKind::Call => write!(f, "call"),
+            EdgeKind::Return => write!(f, "return"),
+            EdgeKind::Halt => write!(f, "halt")
+        }
+    }
+}
+
+// from and to are indices into the blocks, with one past the last block
+// standing for the end of the script
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind
+}
+
+// the control flow graph of a script, worked out from the flow of its
+// instructions and its structured blocks
+pub struct Cfg<'a, I: Clone> {
+    script: &'a Script<I>,
+    blocks: Vec<BasicBlock>,
+    edges: Vec<Edge>
+}
+
+impl<'a, I: Clone + Instruction<I>> Cfg<'a, I> {
+    pub fn new(script: &'a Script<I>) -> Self {
+        let len = script.len();
+
+        // blocks start at the start, at anything jumped to or named and
+        // after anything that doesn't just go on to the next instruction
+        let mut starts: BTreeSet<usize> = script.labels().values().copied().collect();
+        starts.insert(0);
+        for ip in 0..len {
+            match script.flow(ip) {
+                Some(Flow::Next) | None => {},
+                Some(f) => {
+                    starts.extend(f.target());
+                    starts.insert(ip + 1);
+                }
+            }
+        }
+        let starts: Vec<usize> = starts.into_iter().filter(|s| *s < len).collect();
+        let blocks: Vec<BasicBlock> = starts.iter().enumerate()
+            .map(|(n, s)| BasicBlock { start: *s, end: starts.get(n + 1).copied().unwrap_or(len) })
+            .collect();
+
+        let mut cfg = Cfg { script, blocks, edges: Vec::new() };
+        for b in 0..cfg.blocks.len() {
+            let last = cfg.blocks[b].end - 1;
+            let mut add = |t: usize, kind| {
+                let to = cfg.block_at(t).unwrap_or(cfg.blocks.len());
+                cfg.edges.push(Edge { from: b, to, kind });
+            };
+            match script.flow(last) {
+                Some(Flow::Next) => add(last + 1, EdgeKind::Next),
+                Some(Flow::Jump(t)) => add(t, EdgeKind::Jump),
+                Some(Flow::Branch(t)) => {
+                    add(last + 1, EdgeKind::Next);
+                    add(t, EdgeKind::Branch);
+                },
+                Some(Flow::Call(t)) => {
+                    add(t, EdgeKind::Call);
+                    add(last + 1, EdgeKind::Return);
+                },
+                Some(Flow::Halt) => add(len, EdgeKind::Halt),
+                Some(Flow::Return) | Some(Flow::Dynamic) | None => {}
+            }
+        }
+        cfg
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    // the index of the block holding the instruction at ip
+    pub fn block_at(&self, ip: usize) -> Option<usize> {
+        self.blocks.iter().position(|b| b.start <= ip && ip < b.end)
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl<'a, I: Clone + Instruction<I> + fmt::Display> Cfg<'a, I> {
+    // the graph in Graphviz DOT format, with every block listing its
+    // instructions; calls are solid and the returns from them dashed
+    pub fn to_dot(&self) -> String {
+        let mut s = String::new();
+        s.push_str("digraph script {\n");
+        s.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for (n, b) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for ip in b.start..b.end {
+                for (name, _) in self.script.labels().iter().filter(|(_, i)| **i == ip) {
+                    label.push_str(&format!(":{}\\l", escape(name)));
+                }
+                if let Some(i) = self.script.get(ip) {
+                    label.push_str(&format!("{}: {}\\l", ip, escape(&i.to_string())));
+                }
+            }
+            s.push_str(&format!("    b{} [label=\"{}\"];\n", n, label));
+        }
+        s.push_str("    end [shape=doublecircle, label=\"end\"];\n");
+        for e in &self.edges {
+            let to = if e.to == self.blocks.len() { "end".to_string() } else { format!("b{}", e.to) };
+            match e.kind {
+                EdgeKind::Next => s.push_str(&format!("    b{} -> {};\n", e.from, to)),
+                EdgeKind::Return => s.push_str(&format!("    b{} -> {} [style=dashed, label=\"{}\"];\n", e.from, to, e.kind)),
+                k => s.push_str(&format!("    b{} -> {} [label=\"{}\"];\n", e.from, to, k))
+            }
+        }
+        s.push_str("}\n");
+        s
+    }
+}
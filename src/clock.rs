@@ -0,0 +1,30 @@
+use std::time::{
+    Duration,
+    Instant
+};
+
+// a monotonic clock measured from an arbitrary starting point, machines only
+// ever look at the difference between two readings
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+pub struct SystemClock(Instant);
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock(Instant::now())
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
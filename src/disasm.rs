@@ -0,0 +1,67 @@
+use crate::{
+    Flow,
+    Instruction,
+    Script,
+    token
+};
+use std::fmt;
+
+// a listing of a script for people to read: one instruction per line with
+// its index, indented by block depth, with labels on their own lines and
+// where each instruction goes when that isn't the next one
+pub struct Disassembly<'a, I: Clone> {
+    script: &'a Script<I>
+}
+
+impl<'a, I: Clone + Instruction<I> + fmt::Display> Disassembly<'a, I> {
+    pub fn new(script: &'a Script<I>) -> Self {
+        Disassembly { script }
+    }
+
+    // an index with the labels that name it, or "end" for the end of the
+    // script
+    fn target(&self, t: usize) -> String {
+        let names: Vec<String> = self.script.labels().iter()
+            .filter(|(_, i)| **i == t)
+            .map(|(n, _)| format!(":{}", n))
+            .collect();
+        let mut s = if t == self.script.len() { "end".to_string() } else { t.to_string() };
+        if !names.is_empty() {
+            s = format!("{} ({})", s, names.join(" "));
+        }
+        s
+    }
+}
+
+impl<'a, I: Clone + Instruction<I> + fmt::Display> fmt::Display for Disassembly<'a, I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = self.script;
+        let w = s.len().to_string().len();
+        for ip in 0..=s.len() {
+            let depth = s.blocks().map(|b| b.depth(ip)).unwrap_or(0);
+            let indent = "    ".repeat(depth);
+            for (name, _) in s.labels().iter().filter(|(_, i)| **i == ip) {
+                writeln!(f, "{:w$}  {}:{}", "", indent, name, w = w)?;
+            }
+            let i = match s.get(ip) {
+                Some(i) => i,
+                None => break
+            };
+            let text = format!("{}{}", indent, token::quote(&i.to_string()));
+            let note = match s.flow(ip) {
+                Some(Flow::Jump(t)) => Some(format!("jump {}", self.target(t))),
+                Some(Flow::Branch(t)) => Some(format!("branch {}", self.target(t))),
+                Some(Flow::Call(t)) => Some(format!("call {}", self.target(t))),
+                Some(Flow::Return) => Some("return".to_string()),
+                Some(Flow::Halt) => Some("halt".to_string()),
+                Some(Flow::Dynamic) => Some("jump ?".to_string()),
+                Some(Flow::Next) | None => None
+            };
+            match note {
+                Some(n) => writeln!(f, "{:>w$}  {:<24} # {}", ip, text, n, w = w)?,
+                None => writeln!(f, "{:>w$}  {}", ip, text, w = w)?
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{
+    flow::{
+        self,
+        FlowError
+    },
+    Instruction,
+    Script,
+    Signature,
+    Type
+};
+
+// how many items an instruction takes off the data stack and how many it
+// puts back
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StackEffect {
+    pub pops: usize,
+    pub pushes: usize
+}
+
+impl StackEffect {
+    pub fn new(pops: usize, pushes: usize) -> Self {
+        StackEffect { pops, pushes }
+    }
+}
+
+// instruction sets that declare their stack effects can have scripts checked
+// without running them
+pub trait Effect {
+    fn effect(&self) -> StackEffect;
+}
+
+// effects only tell how deep the stack is so the only problems are with the
+// flow of the script
+pub type EffectError = FlowError;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackReport {
+    // the deepest the data stack gets, including inside subroutines
+    pub max_depth: usize,
+    // the depth before each instruction and, last, at the end of the script;
+    // None for anything only reached through a call or not at all
+    pub depths: Vec<Option<usize>>
+}
+
+// works out the depth of the data stack everywhere in the script, following
+// jumps, branches, blocks and calls, and fails on anything that could
+// underflow or that leaves the stack at different depths depending on the
+// path taken
+pub fn analyze<I: Clone + Instruction<I> + Effect>(script: &Script<I>) -> Result<StackReport, EffectError> {
+    // the walk without any types, only how many items there are
+    let w = flow::walk(script, |i: &I| {
+        let e = i.effect();
+        Signature::<()>::new(vec![Type::Any; e.pops], vec![Type::Any; e.pushes])
+    }, |_, _, _| {})?;
+    Ok(StackReport {
+        max_depth: w.peak,
+        depths: (0..=script.len()).map(|ip| w.states.get(&(vec![], ip)).map(|s| s.len())).collect()
+    })
+}
@@ -0,0 +1,106 @@
+use crate::BlockError;
+use std::{
+    clone::Clone,
+    convert::From,
+    error,
+    fmt,
+    io,
+    time::Duration
+};
+
+#[derive(Debug)]
+pub enum Cause {
+    StackUnderflow,
+    StackOverflow,
+    ReturnStackUnderflow,
+    ReturnStackOverflow,
+    TypeMismatch,
+    InvalidInstruction,
+    Block(BlockError),
+    OutOfGas { consumed: u64, remaining: u64 },
+    Cancelled,
+    DeadlineExceeded { elapsed: Duration },
+    Io(io::Error),
+    Other(String)
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cause::StackUnderflow => write!(f, "stack underflow"),
+            Cause::StackOverflow => write!(f, "stack overflow"),
+            Cause::ReturnStackUnderflow => write!(f, "return stack underflow"),
+            Cause::ReturnStackOverflow => write!(f, "return stack overflow"),
+            Cause::TypeMismatch => write!(f, "type mismatch"),
+            Cause::InvalidInstruction => write!(f, "invalid instruction"),
+            Cause::Block(e) => write!(f, "{}", e),
+            Cause::OutOfGas { consumed, remaining } => {
+                write!(f, "out of gas ({} consumed, {} remaining)", consumed, remaining)
+            },
+            Cause::Cancelled => write!(f, "cancelled"),
+            Cause::DeadlineExceeded { elapsed } => {
+                write!(f, "deadline exceeded after {:?}", elapsed)
+            },
+            Cause::Io(e) => write!(f, "io error: {}", e),
+            Cause::Other(s) => write!(f, "{}", s)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MachineError<I: Clone> {
+    pub ip: usize,
+    pub instr: Option<I>,
+    pub cause: Cause
+}
+
+impl<I: Clone> MachineError<I> {
+    pub fn new(cause: Cause) -> Self {
+        Self {
+            ip: 0,
+            instr: None,
+            cause
+        }
+    }
+
+    // the machine calls this with the instruction that failed so that
+    // instructions can return bare causes with `?`
+    pub fn at(mut self, ip: usize, instr: &I) -> Self {
+        if self.instr.is_none() {
+            self.ip = ip;
+            self.instr = Some(instr.clone());
+        }
+        self
+    }
+}
+
+impl<I: Clone> From<Cause> for MachineError<I> {
+    fn from(c: Cause) -> Self {
+        MachineError::new(c)
+    }
+}
+
+impl<I: Clone> From<io::Error> for MachineError<I> {
+    fn from(e: io::Error) -> Self {
+        MachineError::new(Cause::Io(e))
+    }
+}
+
+impl<I: Clone + fmt::Display> fmt::Display for MachineError<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.instr {
+            Some(i) => write!(f, "'{}' at {}: {}", i, self.ip, self.cause),
+            None => write!(f, "at {}: {}", self.ip, self.cause)
+        }
+    }
+}
+
+impl<I: Clone + fmt::Debug + fmt::Display> error::Error for MachineError<I> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.cause {
+            Cause::Block(e) => Some(e),
+            Cause::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
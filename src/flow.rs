@@ -0,0 +1,229 @@
+use crate::{
+    BlockError,
+    Instruction,
+    Script,
+    Signature,
+    Type
+};
+use std::{
+    cmp,
+    collections::BTreeMap,
+    error,
+    fmt
+};
+
+// where execution can go after an instruction, as far as can be told without
+// running it; targets are instruction indices
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    // on to the next instruction
+    Next,
+    // always to the target
+    Jump(usize),
+    // either to the target or on to the next instruction
+    Branch(usize),
+    // to the target, coming back to the next instruction
+    Call(usize),
+    // back to wherever the return stack says
+    Return,
+    // stops the machine
+    Halt,
+    // somewhere that depends on the data stack
+    Dynamic
+}
+
+impl Flow {
+    // the instruction index named in the flow, if any
+    pub fn target(&self) -> Option<usize> {
+        match self {
+            Flow::Jump(t) | Flow::Branch(t) | Flow::Call(t) => Some(*t),
+            _ => None
+        }
+    }
+
+    // the instructions that can run next when this flow is at ip; a call goes
+    // to its target and nothing is known to follow a return, halt or
+    // dynamic flow
+    pub fn successors(&self, ip: usize) -> Vec<usize> {
+        match self {
+            Flow::Next => vec![ip + 1],
+            Flow::Jump(t) | Flow::Call(t) => vec![*t],
+            Flow::Branch(t) if *t == ip + 1 => vec![*t],
+            Flow::Branch(t) => vec![ip + 1, *t],
+            Flow::Return | Flow::Halt | Flow::Dynamic => vec![]
+        }
+    }
+}
+
+// the problems with the shape of a script that stop it being followed from
+// start to finish without running it; a height is the depth of the stack
+// relative to the start of the script
+#[derive(Clone, Debug, PartialEq)]
+pub enum FlowError {
+    Block(BlockError),
+    Underflow { index: usize, depth: usize, takes: usize },
+    Height { index: usize, expected: usize, found: usize },
+    TargetOutOfRange { index: usize, target: usize },
+    Dynamic(usize),
+    Recursive(usize),
+    Return(usize)
+}
+
+impl FlowError {
+    // the index of the instruction where the problem was found
+    pub fn index(&self) -> usize {
+        match self {
+            FlowError::Block(e) => e.index(),
+            FlowError::Underflow { index, .. } |
+            FlowError::Height { index, .. } |
+            FlowError::TargetOutOfRange { index, .. } => *index,
+            FlowError::Dynamic(i) |
+            FlowError::Recursive(i) |
+            FlowError::Return(i) => *i
+        }
+    }
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlowError::Block(e) => write!(f, "{}", e),
+            FlowError::Underflow { index, depth, takes } => write!(f, "instruction at {} takes {} items from a stack of {}", index, takes, depth),
+            FlowError::Height { index, expected, found } => write!(f, "stack is {} deep on one path to {} and {} on another", expected, index, found),
+            FlowError::TargetOutOfRange { index, target } => write!(f, "instruction at {} goes to {} outside the script", index, target),
+            FlowError::Dynamic(i) => write!(f, "instruction at {} goes somewhere that can't be worked out", i),
+            FlowError::Recursive(i) => write!(f, "instruction at {} makes a recursive call", i),
+            FlowError::Return(i) => write!(f, "instruction at {} returns without being called", i)
+        }
+    }
+}
+
+impl error::Error for FlowError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FlowError::Block(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+// the types an item on the stack could have, None when it could be anything
+pub(crate) type Value<T> = Option<Vec<T>>;
+
+fn union<T: Clone + PartialEq>(a: &Value<T>, b: &Value<T>) -> Value<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            let mut u = a.clone();
+            for t in b {
+                if !u.contains(t) {
+                    u.push(t.clone());
+                }
+            }
+            Some(u)
+        },
+        _ => None
+    }
+}
+
+// where a walk is: the return addresses of the calls it is inside and the
+// index of the next instruction
+pub(crate) type Place = (Vec<usize>, usize);
+
+// what could be on the stack at every place a walk reached, and the deepest
+// the stack got along the way
+pub(crate) struct Walk<T> {
+    pub states: BTreeMap<Place, Vec<Value<T>>>,
+    pub peak: usize
+}
+
+// follows every path through the script, through blocks, jumps and calls,
+// working out what could be on the stack from the signature of each
+// instruction. check is shown what each instruction takes and what it would
+// be given; problems with the shape of the script stop the walk
+pub(crate) fn walk<I, T, S, C>(script: &Script<I>, signature: S, mut check: C) -> Result<Walk<T>, FlowError>
+where
+    I: Clone + Instruction<I>,
+    T: Clone + PartialEq,
+    S: Fn(&I) -> Signature<T>,
+    C: FnMut(usize, &[Type<T>], &[Value<T>])
+{
+    if let Err(e) = script.blocks() {
+        return Err(FlowError::Block(e.clone()));
+    }
+
+    let len = script.len();
+    let mut w = Walk { states: BTreeMap::new(), peak: 0 };
+    let mut work: Vec<Place> = vec![(vec![], 0)];
+    w.states.insert((vec![], 0), vec![]);
+
+    while let Some(place) = work.pop() {
+        let (ctx, ip) = place.clone();
+        let mut stack = w.states[&place].clone();
+        let (i, flow) = match (script.get(ip), script.flow(ip)) {
+            (Some(i), Some(f)) => (i, f),
+            // running off the end finishes the machine
+            _ => continue
+        };
+
+        let sig = signature(&i);
+        if stack.len() < sig.takes.len() {
+            return Err(FlowError::Underflow { index: ip, depth: stack.len(), takes: sig.takes.len() });
+        }
+        let taken = stack.split_off(stack.len() - sig.takes.len());
+        check(ip, &sig.takes, &taken);
+        for g in &sig.gives {
+            stack.push(match g {
+                Type::Is(t) => Some(vec![t.clone()]),
+                Type::OneOf(ts) => Some(ts.clone()),
+                Type::Any => None,
+                Type::Same(n) => taken.get(*n).cloned().unwrap_or(None)
+            });
+        }
+        w.peak = cmp::max(w.peak, stack.len());
+
+        let next: Vec<Place> = match flow {
+            Flow::Call(t) => {
+                if ctx.contains(&(ip + 1)) {
+                    return Err(FlowError::Recursive(ip));
+                }
+                let mut c = ctx.clone();
+                c.push(ip + 1);
+                vec![(c, t)]
+            },
+            Flow::Return => {
+                let mut c = ctx.clone();
+                match c.pop() {
+                    Some(r) => vec![(c, r)],
+                    None => return Err(FlowError::Return(ip))
+                }
+            },
+            Flow::Dynamic => return Err(FlowError::Dynamic(ip)),
+            f => f.successors(ip).into_iter().map(|t| (ctx.clone(), t)).collect()
+        };
+
+        for (c, t) in next {
+            if t > len {
+                return Err(FlowError::TargetOutOfRange { index: ip, target: t });
+            }
+            let key = (c, t);
+            match w.states.get_mut(&key) {
+                None => {
+                    w.states.insert(key.clone(), stack.clone());
+                    work.push(key);
+                },
+                Some(old) if old.len() != stack.len() => {
+                    return Err(FlowError::Height { index: t, expected: old.len(), found: stack.len() });
+                },
+                Some(old) => {
+                    // widen what could be there and look again if it changed
+                    let merged: Vec<Value<T>> = old.iter().zip(&stack).map(|(a, b)| union(a, b)).collect();
+                    if merged != *old {
+                        *old = merged;
+                        work.push(key);
+                    }
+                }
+            }
+        }
+    }
+    Ok(w)
+}
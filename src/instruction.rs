@@ -1,9 +1,40 @@
 use crate::{
     AppIO,
-    Machine
+    Block,
+    Flow,
+    Machine,
+    MachineError
 };
 use std::clone::Clone;
 
 pub trait Instruction<I: Clone> {
-    fn execute(&self, ip: usize, m: &mut Machine<I>, io: &dyn AppIO<I>);
+    // runs the instruction and says where to go next. Machine::push and
+    // pushr don't fail on overflow, they drop the item and the machine only
+    // reports it once this returns, so anything done after them still
+    // happens; use try_push and try_pushr with ? to stop at the overflow
+    fn execute(&self, ip: usize, m: &mut Machine<I>, io: &dyn AppIO<I>) -> Result<(), MachineError<I>>;
+
+    // the amount of fuel executing this instruction uses up
+    fn cost(&self) -> u64 {
+        1
+    }
+
+    // instructions that open, split or close structured blocks say so here
+    // and the script works out where the matching instructions are
+    fn block(&self) -> Option<Block> {
+        None
+    }
+
+    // instructions that jump, call, return or stop say so here so scripts can
+    // be checked and analysed without running them
+    fn flow(&self) -> Flow {
+        Flow::Next
+    }
+
+    // a script hands a quoted token here first, with the quotes taken off and
+    // the escapes undone, so an instruction set can tell "IF" from IF. None
+    // reads it like any other token
+    fn quoted(_text: &str) -> Option<I> where Self: Sized {
+        None
+    }
 }
@@ -1,18 +1,140 @@
+pub mod block;
+pub use crate::block::{
+	Block,
+	BlockError,
+	BlockInfo,
+	BlockTable
+};
+
+pub mod bytecode;
+pub use crate::bytecode::{
+	Bytecode,
+	BytecodeError,
+	Decoder,
+	Encoder
+};
+
+pub mod cancel;
+pub use crate::cancel::CancelToken;
+
+pub mod cfg;
+pub use crate::cfg::{
+	BasicBlock,
+	Cfg,
+	Edge,
+	EdgeKind
+};
+
+pub mod clock;
+pub use crate::clock::{
+	Clock,
+	SystemClock
+};
+
+pub mod disasm;
+pub use crate::disasm::Disassembly;
+
+pub mod effect;
+pub use crate::effect::{
+	Effect,
+	EffectError,
+	StackEffect,
+	StackReport
+};
+
+pub mod error;
+pub use crate::error::{
+	Cause,
+	MachineError
+};
+
+pub mod flow;
+pub use crate::flow::{
+	Flow,
+	FlowError
+};
+
 pub mod instruction;
 pub use crate::instruction::Instruction;
 
+// #[derive(Instruction)] with the derive feature
+#[cfg(feature = "derive")]
+pub use gsm_derive::Instruction;
+
+// the derived code names serde through here so users needn't depend on it
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub use serde as __serde;
+
+pub mod literal;
+pub use crate::literal::{
+	Literal,
+	Literals
+};
+
 pub mod machine;
 pub use crate::machine::{
 	Machine,
-	MachineBuilder
+	MachineBuilder,
+	Step
+};
+
+pub mod optimize;
+pub use crate::optimize::{
+	Edit,
+	Optimize,
+	Optimizer,
+	Pass
+};
+
+// a ready-made instruction set with the reference feature
+#[cfg(feature = "reference")]
+pub mod reference;
+
+pub mod repl;
+pub use crate::repl::{
+	Repl,
+	ReplError,
+	Reply
 };
 
 pub mod script;
-pub use crate::script::Script;
+pub use crate::script::{
+	LabelError,
+	ParseError,
+	Script
+};
+
+pub mod snapshot;
+pub use crate::snapshot::MachineSnapshot;
 
 pub mod stack;
 pub use crate::stack::Stack;
 
+mod token;
+
+pub mod trace;
+pub use crate::trace::{
+	IoOp,
+	JsonTracer,
+	Tracer
+};
+
+pub mod typed;
+pub use crate::typed::{
+	Signature,
+	Type,
+	Typed,
+	TypeError
+};
+
+pub mod verify;
+pub use crate::verify::{
+	Verifier,
+	Verify,
+	VerifyError
+};
+
 pub mod appio;
 pub use crate::appio::{
 	AppIO,
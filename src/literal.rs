@@ -0,0 +1,241 @@
+use crate::{
+    Mode,
+    ModeVisitor,
+    Whence,
+    WhenceVisitor,
+    token
+};
+use semver::Version;
+use serde::de::{
+    value,
+    Visitor
+};
+use std::{
+    convert::TryFrom,
+    fmt
+};
+
+// parsers for the values instruction sets usually have literals for. each
+// one takes a whole token and returns None if it isn't one of its values
+
+pub fn bool(s: &str) -> Option<bool> {
+    match s {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None
+    }
+}
+
+// the digits after an optional 0x, 0o or 0b radix prefix
+fn radix(s: &str) -> (u32, &str) {
+    let prefixes = [("0x", 16), ("0X", 16), ("0o", 8), ("0O", 8), ("0b", 2), ("0B", 2)];
+    for (p, r) in prefixes.iter() {
+        if let Some(d) = s.strip_prefix(p) {
+            return (*r, d);
+        }
+    }
+    (10, s)
+}
+
+fn magnitude(s: &str) -> Option<u64> {
+    let (r, digits) = radix(s);
+    // from_str_radix takes a sign of its own, which would allow 0x-1
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(r)) {
+        return None;
+    }
+    u64::from_str_radix(digits, r).ok()
+}
+
+// an optional sign then decimal digits or digits after a radix prefix
+pub fn int(s: &str) -> Option<i64> {
+    match s.strip_prefix('-') {
+        Some(m) => match magnitude(m)? {
+            n if n == i64::MIN.unsigned_abs() => Some(i64::MIN),
+            n => i64::try_from(n).ok().map(|n| -n)
+        },
+        None => i64::try_from(magnitude(s.strip_prefix('+').unwrap_or(s))?).ok()
+    }
+}
+
+pub fn uint(s: &str) -> Option<u64> {
+    magnitude(s.strip_prefix('+').unwrap_or(s))
+}
+
+// 0x followed by two hex digits for every byte
+pub fn bytes(s: &str) -> Option<Vec<u8>> {
+    let h = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"))?;
+    hex::decode(h).ok()
+}
+
+// text in double quotes with the escapes scripts use, or any other text as
+// it is. scripts take the quotes off before Instruction::quoted so this is
+// for text from elsewhere, such as the elements of a sequence
+pub fn text(s: &str) -> Option<String> {
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        let toks = token::tokenize(s).ok()?;
+        match toks.as_slice() {
+            [t] if t.quoted && t.offset == 0 => return Some(t.text.to_string()),
+            _ => return None
+        }
+    }
+    Some(s.to_string())
+}
+
+// text in double quotes with escapes, which text reads back
+pub fn quote(s: &str) -> String {
+    token::quote_always(s)
+}
+
+// whether text can be a token in a script without quotes. text that can
+// still has to be quoted if it would be read as something else, such as a
+// keyword or a number
+pub fn bare(s: &str) -> bool {
+    !token::needs_quotes(s)
+}
+
+pub fn version(s: &str) -> Option<Version> {
+    Version::parse(s).ok()
+}
+
+// Mode takes an empty string to mean no flags, which is no use as a token
+pub fn mode(s: &str) -> Option<Mode> {
+    if s.is_empty() {
+        return None;
+    }
+    ModeVisitor.visit_str::<value::Error>(s).ok()
+}
+
+pub fn whence(s: &str) -> Option<Whence> {
+    WhenceVisitor.visit_str::<value::Error>(s).ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Bool,
+    Int,
+    Uint,
+    Bytes,
+    Text,
+    Version,
+    Mode,
+    Whence
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal {
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Version(Version),
+    Mode(Mode),
+    Whence(Whence)
+}
+
+impl Literal {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Literal::Bool(_) => Kind::Bool,
+            Literal::Int(_) => Kind::Int,
+            Literal::Uint(_) => Kind::Uint,
+            Literal::Bytes(_) => Kind::Bytes,
+            Literal::Text(_) => Kind::Text,
+            Literal::Version(_) => Kind::Version,
+            Literal::Mode(_) => Kind::Mode,
+            Literal::Whence(_) => Kind::Whence
+        }
+    }
+}
+
+// written so that the standard Literals parse it back to the same value,
+// quoting text that would otherwise be read as something else
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Literal::Text(s) if !bare(s) || Literals::standard().parse(s).as_ref() != Some(self) => {
+                write!(f, "{}", quote(s))
+            },
+            Literal::Bool(b) => write!(f, "{}", b),
+            Literal::Int(n) => write!(f, "{}", n),
+            Literal::Uint(n) => write!(f, "{}", n),
+            Literal::Bytes(b) => write!(f, "0x{}", hex::encode(b)),
+            Literal::Text(s) => write!(f, "{}", s),
+            Literal::Version(v) => write!(f, "{}", v),
+            Literal::Mode(m) => write!(f, "{}", m),
+            Literal::Whence(w) => write!(f, "{}", w)
+        }
+    }
+}
+
+// tries parsers in a fixed order and takes the first that accepts a token,
+// so the order decides what a token that more than one would accept means
+#[derive(Clone, Debug, PartialEq)]
+pub struct Literals {
+    kinds: Vec<Kind>
+}
+
+impl Literals {
+    // accepts nothing until kinds are added
+    pub fn new() -> Self {
+        Literals { kinds: Vec::new() }
+    }
+
+    // bool, bytes, int, uint, version then text, so 0x always starts bytes
+    // and 0x tokens that aren't whole bytes are rejected, numbers too big for
+    // an i64 are uints and anything else is text. modes
+    // and whences take plain words like "bar" and "END" so they have to be
+    // asked for
+    pub fn standard() -> Self {
+        let mut l = Self::new();
+        l.kind(Kind::Bool)
+         .kind(Kind::Bytes)
+         .kind(Kind::Int)
+         .kind(Kind::Uint)
+         .kind(Kind::Version)
+         .kind(Kind::Text);
+        l
+    }
+
+    // adds a kind after the ones already there, so it is tried last
+    pub fn kind(&mut self, k: Kind) -> &mut Self {
+        if !self.kinds.contains(&k) {
+            self.kinds.push(k);
+        }
+        self
+    }
+
+    pub fn kinds(&self) -> &[Kind] {
+        &self.kinds
+    }
+
+    pub fn parse(&self, s: &str) -> Option<Literal> {
+        for k in &self.kinds {
+            // bytes claims every 0x token it is asked about, so one with an odd
+            // number of digits isn't read as an int or text instead
+            if *k == Kind::Bytes && (s.starts_with("0x") || s.starts_with("0X")) {
+                return bytes(s).map(Literal::Bytes);
+            }
+            let l = match k {
+                Kind::Bool => bool(s).map(Literal::Bool),
+                Kind::Int => int(s).map(Literal::Int),
+                Kind::Uint => uint(s).map(Literal::Uint),
+                Kind::Bytes => bytes(s).map(Literal::Bytes),
+                Kind::Text => text(s).map(Literal::Text),
+                Kind::Version => version(s).map(Literal::Version),
+                Kind::Mode => mode(s).map(Literal::Mode),
+                Kind::Whence => whence(s).map(Literal::Whence)
+            };
+            if l.is_some() {
+                return l;
+            }
+        }
+        None
+    }
+}
+
+impl Default for Literals {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
@@ -1,26 +1,52 @@
 use crate::{
     AppIO,
+    BlockInfo,
+    CancelToken,
+    Cause,
+    Clock,
     Instruction,
+    MachineError,
+    MachineSnapshot,
     Script,
-    Stack
+    Stack,
+    SystemClock,
+    Tracer,
+    trace::TracedIO
 };
 use semver::{
     Version,
     VersionReq
 };
-use std::convert::From;
+use std::{
+    cell::RefCell,
+    convert::From,
+    rc::Rc,
+    time::Duration
+};
 
 pub struct MachineBuilder<I: Clone>
 {
     s: Script<I>,
-    v: VersionReq
+    v: VersionReq,
+    fuel: Option<u64>,
+    max_d: Option<usize>,
+    max_r: Option<usize>,
+    tracer: Option<Rc<RefCell<dyn Tracer<I>>>>,
+    deadline: Option<Duration>,
+    clock: Option<Rc<dyn Clock>>
 }
 
 impl<I: Clone + Instruction<I>> MachineBuilder<I> {
     pub fn new() -> Self {
         Self {
             s: Script::from(Vec::new()),
-            v: VersionReq::any()
+            v: VersionReq::any(),
+            fuel: None,
+            max_d: None,
+            max_r: None,
+            tracer: None,
+            deadline: None,
+            clock: None
         }
     }
 
@@ -34,32 +60,151 @@ impl<I: Clone + Instruction<I>> MachineBuilder<I> {
         self
     }
 
+    // limits the total cost of the instructions the machine will execute
+    pub fn fuel(&mut self, f: u64) -> &mut Self {
+        self.fuel = Some(f);
+        self
+    }
+
+    pub fn max_stack(&mut self, n: usize) -> &mut Self {
+        self.max_d = Some(n);
+        self
+    }
+
+    pub fn max_return_stack(&mut self, n: usize) -> &mut Self {
+        self.max_r = Some(n);
+        self
+    }
+
+    pub fn tracer(&mut self, t: Rc<RefCell<dyn Tracer<I>>>) -> &mut Self {
+        self.tracer = Some(t);
+        self
+    }
+
+    // limits how long the machine runs, starting from the first step
+    pub fn deadline(&mut self, d: Duration) -> &mut Self {
+        self.deadline = Some(d);
+        self
+    }
+
+    pub fn clock(&mut self, c: Rc<dyn Clock>) -> &mut Self {
+        self.clock = Some(c);
+        self
+    }
+
     pub fn build(&self) -> Machine<I> {
-        Machine::new(&self.s, &self.v)
+        Machine::new(self)
     }
 }
 
+impl<I: Clone + Instruction<I>> Default for MachineBuilder<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Step<I: Clone> {
+    Running,
+    Finished(Stack<I>),
+    Halted
+}
+
 pub struct Machine<I: Clone>
 {
     v: VersionReq,
     d: Stack<I>,
     r: Stack<usize>,
-    s: Script<I>
+    s: Script<I>,
+    ip: usize,
+    fuel: Option<u64>,
+    consumed: u64,
+    max_d: Option<usize>,
+    max_r: Option<usize>,
+    overflow: Option<Cause>,
+    tracer: Option<Rc<RefCell<dyn Tracer<I>>>>,
+    cancel: CancelToken,
+    deadline: Option<Duration>,
+    clock: Rc<dyn Clock>,
+    started: Option<Duration>,
+    halted: bool
 }
 
 impl<I: Clone + Instruction<I>> Machine<I>
 {
-    fn new(s: &Script<I>, v: &VersionReq) -> Self {
+    fn new(b: &MachineBuilder<I>) -> Self {
         Self {
-            v: v.clone(),
+            v: b.v.clone(),
             d: Stack::<I>::new(),
             r: Stack::from(vec![0]),
-            s: s.clone()
+            s: b.s.clone(),
+            ip: 0,
+            fuel: b.fuel,
+            consumed: 0,
+            max_d: b.max_d,
+            max_r: b.max_r,
+            overflow: None,
+            tracer: b.tracer.clone(),
+            cancel: CancelToken::new(),
+            deadline: b.deadline,
+            clock: match &b.clock {
+                Some(c) => c.clone(),
+                None => Rc::new(SystemClock::new())
+            },
+            started: None,
+            halted: false
+        }
+    }
+
+    // restores a machine from a snapshot with the limits, tracer, deadline
+    // and clock from the builder; the script and version requirement come
+    // from the snapshot. stacks deeper than the builder allows overflow
+    pub fn from_snapshot(snap: MachineSnapshot<I>, b: &MachineBuilder<I>) -> Result<Self, MachineError<I>> {
+        if matches!(b.max_d, Some(max) if snap.stack.size() > max) {
+            return Err(Cause::StackOverflow.into());
         }
+        if matches!(b.max_r, Some(max) if snap.rstack.size() > max) {
+            return Err(Cause::ReturnStackOverflow.into());
+        }
+        let mut m = Machine::new(b);
+        m.v = snap.version_req;
+        m.s = snap.script;
+        m.d = snap.stack;
+        m.r = snap.rstack;
+        m.consumed = snap.consumed;
+        m.halted = snap.halted;
+        Ok(m)
     }
 
+    pub fn snapshot(&self) -> MachineSnapshot<I> {
+        MachineSnapshot {
+            version_req: self.v.clone(),
+            stack: self.d.clone(),
+            rstack: self.r.clone(),
+            script: self.s.clone(),
+            consumed: self.consumed,
+            halted: self.halted
+        }
+    }
+
+    // pushing past the depth limit drops the item and the machine reports
+    // the overflow once the current instruction returns
     pub fn push(&mut self, i: I) {
-        self.d.push(i);
+        if self.try_push(i).is_err() {
+            self.overflow.get_or_insert(Cause::StackOverflow);
+        }
+    }
+
+    // like push but the overflow comes back straight away, so an instruction
+    // can stop with ? before it does anything else
+    pub fn try_push(&mut self, i: I) -> Result<(), MachineError<I>> {
+        match self.max_d {
+            Some(max) if self.d.size() >= max => Err(Cause::StackOverflow.into()),
+            _ => {
+                self.d.push(i);
+                Ok(())
+            }
+        }
     }
 
     pub fn pop(&mut self) -> Option<I> {
@@ -67,51 +212,248 @@ impl<I: Clone + Instruction<I>> Machine<I>
     }
 
     pub fn pushr(&mut self, i: usize) {
-        self.r.push(i);
+        if self.try_pushr(i).is_err() {
+            self.overflow.get_or_insert(Cause::ReturnStackOverflow);
+        }
+    }
+
+    pub fn try_pushr(&mut self, i: usize) -> Result<(), MachineError<I>> {
+        match self.max_r {
+            Some(max) if self.r.size() >= max => Err(Cause::ReturnStackOverflow.into()),
+            _ => {
+                self.r.push(i);
+                Ok(())
+            }
+        }
     }
 
     pub fn popr(&mut self) -> Option<usize> {
         self.r.pop()
     }
 
+    // continue with the instruction after ip
+    pub fn next(&mut self, ip: usize) {
+        self.pushr(ip + 1);
+    }
+
+    // continue with the instruction at target
+    pub fn jump(&mut self, target: usize) {
+        self.pushr(target);
+    }
+
+    // continue with the instruction at target and come back to return_to
+    // once the code there calls ret()
+    pub fn call(&mut self, target: usize, return_to: usize) {
+        self.pushr(return_to);
+        self.pushr(target);
+    }
+
+    // continue with the instruction saved by the matching call(), which is
+    // already on top of the return stack
+    pub fn ret(&mut self) -> Result<(), MachineError<I>> {
+        match self.current_ip() {
+            Some(_) => Ok(()),
+            None => Err(Cause::ReturnStackUnderflow.into())
+        }
+    }
+
+    // stop the machine once the current instruction returns
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn stack(&self) -> &Stack<I> {
+        &self.d
+    }
+
+    pub fn return_stack(&self) -> &Stack<usize> {
+        &self.r
+    }
+
     pub fn geti(&self, i: usize) -> Option<I> {
         self.s.get(i)
     }
 
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.s.label(name)
+    }
+
+    // the structured block that the instruction at ip opens, splits or closes
+    pub fn block(&self, ip: usize) -> Option<BlockInfo> {
+        self.s.blocks().ok().and_then(|b| b.get(ip))
+    }
+
     pub fn version_check(&self, v: &Version) -> bool {
         self.v.matches(v)
     }
 
+    pub fn current_ip(&self) -> Option<usize> {
+        self.r.top().copied()
+    }
+
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    pub fn fuel_consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    pub fn fuel_remaining(&self) -> Option<u64> {
+        self.fuel.map(|f| f.saturating_sub(self.consumed))
+    }
+
+    // tops up the fuel of a machine that ran out so it can continue
+    pub fn add_fuel(&mut self, f: u64) {
+        if let Some(fuel) = self.fuel.as_mut() {
+            *fuel = fuel.saturating_add(f);
+        }
+    }
+
     pub fn reset(&mut self) {
         self.d = Stack::<I>::new();
         self.r = Stack::<usize>::new();
+        self.ip = 0;
+        self.consumed = 0;
+        self.overflow = None;
+        self.started = None;
+        self.halted = false;
+        self.pushr(0);
+    }
+
+    // swaps in another script and starts it from the beginning, keeping the
+    // data stack and the fuel used so far
+    pub fn load(&mut self, s: &Script<I>) {
+        self.s = s.clone();
+        self.r = Stack::<usize>::new();
+        self.ip = 0;
+        self.overflow = None;
+        self.started = None;
+        self.halted = false;
         self.pushr(0);
     }
 
-    pub fn execute(&mut self, io: &dyn AppIO<I>) -> Option<Stack<I>>
+    pub fn set_stack(&mut self, d: Stack<I>) {
+        self.d = d;
+    }
+
+    pub fn step(&mut self, io: &dyn AppIO<I>) -> Result<Step<I>, MachineError<I>>
     {
-        loop {
-            if let Some(ip) = self.popr() {
-                if let Some(instr) = self.geti(ip) {
-                    instr.execute(ip, self, io);
+        if self.halted {
+            return Ok(Step::Halted);
+        }
+
+        if self.cancel.is_cancelled() {
+            return Err(self.stopped(Cause::Cancelled));
+        }
+
+        // a script with badly nested blocks never runs
+        if let Err(e) = self.s.blocks() {
+            let ip = e.index();
+            let err = MachineError::new(Cause::Block(e.clone()));
+            return Err(match self.geti(ip) {
+                Some(instr) => err.at(ip, &instr),
+                None => MachineError { ip, ..err }
+            });
+        }
+
+        if let Some(limit) = self.deadline {
+            let now = self.clock.now();
+            let elapsed = now.saturating_sub(*self.started.get_or_insert(now));
+            if elapsed > limit {
+                return Err(self.stopped(Cause::DeadlineExceeded { elapsed }));
+            }
+        }
+
+        // an empty return stack means an instruction forgot to say where
+        // to go next
+        let ip = match self.popr() {
+            Some(ip) => ip,
+            None => {
+                let e = MachineError::new(Cause::ReturnStackUnderflow);
+                return Err(MachineError { ip: self.ip, ..e });
+            }
+        };
+
+        match self.geti(ip) {
+            Some(instr) => {
+                let cost = instr.cost();
+                if let Some(remaining) = self.fuel_remaining() {
+                    if cost > remaining {
+                        // leave the machine where it was so it can be resumed
+                        self.pushr(ip);
+                        let e = MachineError::new(Cause::OutOfGas {
+                            consumed: self.consumed,
+                            remaining
+                        });
+                        return Err(e.at(ip, &instr));
+                    }
+                }
+                self.consumed = self.consumed.saturating_add(cost);
+                self.ip = ip;
+                let r = match self.tracer.clone() {
+                    Some(t) => {
+                        t.borrow_mut().before(ip, &instr, &self.d, &self.r);
+                        let tio = TracedIO { io, tracer: t };
+                        instr.execute(ip, self, &tio)
+                    },
+                    None => instr.execute(ip, self, io)
+                };
+
+                // an overflow is the root cause of whatever else went wrong
+                if let Some(c) = self.overflow.take() {
+                    return Err(MachineError::new(c).at(ip, &instr));
+                }
+                r.map_err(|e| e.at(ip, &instr))?;
+                if let Some(t) = &self.tracer {
+                    t.borrow_mut().after(ip, &instr, &self.d, &self.r);
+                }
+                if self.halted {
+                    Ok(Step::Halted)
                 } else {
-                    // end of script
-                    return Some(self.d.clone());
+                    Ok(Step::Running)
                 }
-            } else {
-                return None;
+            },
+            None => {
+                // end of script, leave the machine pointing at the end so
+                // that stepping again reports the same thing
+                self.pushr(ip);
+                Ok(Step::Finished(self.d.clone()))
+            }
+        }
+    }
+
+    // an error for stopping before the next instruction without changing
+    // the state of the machine
+    fn stopped(&self, c: Cause) -> MachineError<I> {
+        let e = MachineError::new(c);
+        match self.current_ip() {
+            Some(ip) => match self.geti(ip) {
+                Some(instr) => e.at(ip, &instr),
+                None => MachineError { ip, ..e }
+            },
+            None => e
+        }
+    }
+
+    pub fn execute(&mut self, io: &dyn AppIO<I>) -> Result<Stack<I>, MachineError<I>>
+    {
+        loop {
+            match self.step(io)? {
+                Step::Running => {},
+                Step::Finished(d) => return Ok(d),
+                Step::Halted => return Ok(self.d.clone())
             }
         }
     }
 }
 
-impl<I: Clone> From<Script<I>> for Machine<I> {
+impl<I: Clone + Instruction<I>> From<Script<I>> for Machine<I> {
     fn from(s: Script<I>) -> Self {
-        Machine {
-            v: VersionReq::any(),
-            d: Stack::<I>::new(),
-            r: Stack::from(vec![0]),
-            s: s
-        }
+        MachineBuilder::new().script(&s).build()
     }
 }
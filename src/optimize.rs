@@ -0,0 +1,324 @@
+use crate::{
+    Block,
+    Effect,
+    Flow,
+    Instruction,
+    Script
+};
+use std::{
+    collections::BTreeSet,
+    rc::Rc
+};
+
+// the hooks an instruction set gives the optimizer; every instruction in the
+// scripts handed to them uses the indices of the script being optimized
+pub trait Optimize: Sized {
+    // instructions that only push a value, which constant folding works on
+    fn is_constant(&self) -> bool {
+        false
+    }
+
+    // the constants left by running this instruction on the constants in
+    // args, listed bottom to top, or None if it can't be worked out
+    fn fold(&self, _args: &[Self]) -> Option<Vec<Self>> {
+        None
+    }
+
+    // for instructions that open a block by testing the top of the stack,
+    // whether the block is entered when cond is on top
+    fn decide(&self, _cond: &Self) -> Option<bool> {
+        None
+    }
+
+    // rewrites the instructions at the start of window, returning how many
+    // are replaced and what replaces them
+    fn peephole(_window: &[Self]) -> Option<(usize, Vec<Self>)> {
+        None
+    }
+
+    // the instruction with any instruction indices it holds passed through
+    // map, for when instructions are added or removed before them
+    fn retarget(&self, _map: &dyn Fn(usize) -> usize) -> Self;
+}
+
+// replaces the instructions from start up to end with others
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit<I> {
+    pub start: usize,
+    pub end: usize,
+    pub with: Vec<I>
+}
+
+impl<I> Edit<I> {
+    pub fn new(start: usize, end: usize, with: Vec<I>) -> Self {
+        Edit { start, end, with }
+    }
+}
+
+pub trait Pass<I: Clone> {
+    // the edits to make, in order and not overlapping
+    fn run(&self, script: &Script<I>) -> Vec<Edit<I>>;
+}
+
+// the indices the instruction at ip can send execution to other than the
+// next one: jump and branch targets and where calls return to
+fn targets<I: Clone + Instruction<I>>(script: &Script<I>, ip: usize) -> Vec<usize> {
+    match script.flow(ip) {
+        Some(Flow::Call(t)) => vec![t, ip + 1],
+        Some(f) => f.target().into_iter().collect(),
+        None => vec![]
+    }
+}
+
+// the indices that execution can arrive at other than from the instruction
+// before, including labels, which can be looked up while running; edits must
+// not remove or split them
+pub fn entries<I: Clone + Instruction<I>>(script: &Script<I>) -> BTreeSet<usize> {
+    let mut e: BTreeSet<usize> = script.labels().values().copied().collect();
+    for ip in 0..script.len() {
+        e.extend(targets(script, ip));
+    }
+    e
+}
+
+// whether the instructions from start up to end can be replaced as a whole,
+// which they can't if anything jumps into the middle of them
+fn straight<I: Clone + Instruction<I>>(script: &Script<I>, entries: &BTreeSet<usize>, start: usize, end: usize) -> bool {
+    entries.range(start + 1..end).next().is_none() &&
+        (start..end).all(|ip| script.get(ip).is_some_and(|i| i.block().is_none()))
+}
+
+// removes the instructions after an unconditional jump, return or halt that
+// nothing jumps to
+pub struct DeadCode;
+
+impl<I: Clone + Instruction<I>> Pass<I> for DeadCode {
+    fn run(&self, script: &Script<I>) -> Vec<Edit<I>> {
+        let entries = entries(script);
+        let mut edits = Vec::new();
+        let mut ip = 0;
+        while ip < script.len() {
+            match script.flow(ip) {
+                Some(Flow::Jump(_)) | Some(Flow::Return) | Some(Flow::Halt) => {},
+                _ => {
+                    ip += 1;
+                    continue;
+                }
+            }
+            let start = ip + 1;
+            let mut end = start;
+            while end < script.len() && !entries.contains(&end) && script.get(end).is_some_and(|i| i.block().is_none()) {
+                end += 1;
+            }
+            if end > start {
+                edits.push(Edit::new(start, end, vec![]));
+            }
+            ip = end;
+        }
+        edits
+    }
+}
+
+// removes the test and the arm that can't run from blocks opened by testing
+// a constant, using Optimize::decide
+pub struct UnreachableBlocks;
+
+impl<I: Clone + Instruction<I> + Optimize> Pass<I> for UnreachableBlocks {
+    fn run(&self, script: &Script<I>) -> Vec<Edit<I>> {
+        let blocks = match script.blocks() {
+            Ok(b) => b,
+            Err(_) => return vec![]
+        };
+        let mut edits = Vec::new();
+        let mut next = 0;
+        for ip in 1..script.len() {
+            let (cond, open) = match (script.get(ip - 1), script.get(ip)) {
+                (Some(c), Some(o)) if c.is_constant() && o.block() == Some(Block::Open) => (c, o),
+                _ => continue
+            };
+            let b = match blocks.get(ip) {
+                Some(b) if ip > next => b,
+                _ => continue
+            };
+            let mut e = match (open.decide(&cond), b.split) {
+                (Some(true), Some(s)) => vec![Edit::new(ip - 1, ip + 1, vec![]), Edit::new(s, b.close + 1, vec![])],
+                (Some(true), None) => vec![Edit::new(ip - 1, ip + 1, vec![]), Edit::new(b.close, b.close + 1, vec![])],
+                (Some(false), Some(s)) => vec![Edit::new(ip - 1, s + 1, vec![]), Edit::new(b.close, b.close + 1, vec![])],
+                (Some(false), None) => vec![Edit::new(ip - 1, b.close + 1, vec![])],
+                (None, _) => continue
+            };
+
+            // nothing that stays may jump into the parts that go
+            let gone = |t: &usize| e.iter().any(|e| e.start < *t && *t < e.end);
+            let kept = |ip: &usize| !e.iter().any(|e| e.start <= *ip && *ip < e.end);
+            if script.labels().values().any(gone) ||
+                (0..script.len()).filter(kept).any(|ip| targets(script, ip).iter().any(gone)) {
+                continue;
+            }
+            next = b.close + 1;
+            edits.append(&mut e);
+        }
+        edits
+    }
+}
+
+// replaces instructions whose arguments are all constants with the
+// constants they leave, using Optimize::fold
+pub struct ConstantFold;
+
+impl<I: Clone + Instruction<I> + Effect + Optimize> Pass<I> for ConstantFold {
+    fn run(&self, script: &Script<I>) -> Vec<Edit<I>> {
+        let entries = entries(script);
+        let mut edits = Vec::new();
+        let mut next = 0;
+        for (ip, i) in script.iter().enumerate() {
+            let n = i.effect().pops;
+            if n == 0 || n > ip || ip - n < next || script.flow(ip) != Some(Flow::Next) {
+                continue;
+            }
+            let start = ip - n;
+            if !straight(script, &entries, start, ip + 1) {
+                continue;
+            }
+            let args: Vec<I> = (start..ip).filter_map(|a| script.get(a)).collect();
+            if !args.iter().all(|a| a.is_constant()) {
+                continue;
+            }
+            if let Some(out) = i.fold(&args) {
+                if out.iter().all(|o| o.is_constant()) {
+                    edits.push(Edit::new(start, ip + 1, out));
+                    next = ip + 1;
+                }
+            }
+        }
+        edits
+    }
+}
+
+// applies the instruction set's rewrite rules from Optimize::peephole
+pub struct Peephole;
+
+impl<I: Clone + Instruction<I> + Optimize> Pass<I> for Peephole {
+    fn run(&self, script: &Script<I>) -> Vec<Edit<I>> {
+        let entries = entries(script);
+        let code: Vec<I> = script.iter().cloned().collect();
+        let mut edits = Vec::new();
+        let mut ip = 0;
+        while ip < code.len() {
+            match I::peephole(&code[ip..]) {
+                Some((n, with)) if n > 0 && ip + n <= code.len() && straight(script, &entries, ip, ip + n) &&
+                    with.iter().all(|w| w.block().is_none()) => {
+                    edits.push(Edit::new(ip, ip + n, with));
+                    ip += n;
+                },
+                _ => ip += 1
+            }
+        }
+        edits
+    }
+}
+
+// makes the edits, moving jump targets and labels to where the instructions
+// they named ended up
+pub fn apply<I: Clone + Instruction<I> + Optimize>(script: &Script<I>, edits: &[Edit<I>]) -> Script<I> {
+    let len = script.len();
+    let mut code: Vec<I> = Vec::with_capacity(len);
+    let mut map: Vec<usize> = vec![0; len + 1];
+    let mut ip = 0;
+    for e in edits {
+        if e.start < ip || e.end < e.start || e.end > len {
+            continue;
+        }
+        for (n, i) in script.iter().enumerate().take(e.start).skip(ip) {
+            map[n] = code.len();
+            code.push(i.clone());
+        }
+        for m in map.iter_mut().take(e.end).skip(e.start) {
+            *m = code.len();
+        }
+        code.extend(e.with.iter().cloned());
+        ip = e.end;
+    }
+    for (n, i) in script.iter().enumerate().skip(ip) {
+        map[n] = code.len();
+        code.push(i.clone());
+    }
+    map[len] = code.len();
+
+    let remap = |t: usize| map.get(t).copied().unwrap_or(t);
+    let code: Vec<I> = code.iter().map(|i| i.retarget(&remap)).collect();
+    let mut s = Script::from(code);
+    s.set_labels(script.labels().iter().map(|(name, index)| (name.clone(), remap(*index))).collect());
+    s
+}
+
+// runs passes over a script until none of them changes it
+pub struct Optimizer<I: Clone> {
+    passes: Vec<Rc<dyn Pass<I>>>,
+    rounds: usize
+}
+
+impl<I: Clone + Instruction<I> + Effect + Optimize + 'static> Optimizer<I> {
+    // an optimizer with no passes
+    pub fn new() -> Self {
+        Optimizer {
+            passes: Vec::new(),
+            rounds: 16
+        }
+    }
+
+    // an optimizer with all of the passes in this module
+    pub fn standard() -> Self {
+        let mut o = Self::new();
+        o.pass(Rc::new(DeadCode))
+         .pass(Rc::new(UnreachableBlocks))
+         .pass(Rc::new(ConstantFold))
+         .pass(Rc::new(Peephole));
+        o
+    }
+
+    pub fn pass(&mut self, p: Rc<dyn Pass<I>>) -> &mut Self {
+        self.passes.push(p);
+        self
+    }
+
+    // limits how many times the passes are run over the script
+    pub fn rounds(&mut self, n: usize) -> &mut Self {
+        self.rounds = n;
+        self
+    }
+
+    // scripts that work out where to jump from the data stack could be
+    // relying on any index so they and badly nested scripts are left alone
+    pub fn optimize(&self, script: &Script<I>) -> Script<I> {
+        let mut s = script.clone();
+        if s.blocks().is_err() || (0..s.len()).any(|ip| s.flow(ip) == Some(Flow::Dynamic)) {
+            return s;
+        }
+        for _ in 0..self.rounds {
+            let mut changed = false;
+            for p in &self.passes {
+                let edits = p.run(&s);
+                if edits.is_empty() {
+                    continue;
+                }
+                let next = apply(&s, &edits);
+                if next.blocks().is_err() {
+                    continue;
+                }
+                s = next;
+                changed = true;
+            }
+            if !changed {
+                break;
+            }
+        }
+        s
+    }
+}
+
+impl<I: Clone + Instruction<I> + Effect + Optimize + 'static> Default for Optimizer<I> {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
@@ -0,0 +1,599 @@
+use crate::{
+    AppIO,
+    Block,
+    Bytecode,
+    BytecodeError,
+    Cause,
+    Decoder,
+    Encoder,
+    Flow,
+    Instruction,
+    Machine,
+    MachineError,
+    Mode,
+    Whence,
+    literal
+};
+use serde::{
+    de::{
+        self,
+        Visitor
+    },
+    Deserialize,
+    Deserializer
+};
+use std::{
+    cell::RefCell,
+    fmt,
+    fs::{
+        File,
+        OpenOptions
+    },
+    io::{
+        self,
+        Read,
+        Seek,
+        SeekFrom,
+        Write
+    },
+    mem,
+    rc::Rc
+};
+
+// a small general purpose instruction set for trying scripts out without
+// writing one: numbers, booleans and text, arithmetic, stack shuffling,
+// IF/ELSE/FI blocks, jumps and calls that take their target from the stack
+// and file io through FileIO
+#[derive(Clone, Debug)]
+pub enum Instr {
+    // values that can be in a script
+    Num(i64),
+    Bool(bool),
+    Text(String),
+    Mode(Mode),
+    Whence(Whence),
+
+    // an open file, which can be on the stack but not in a script
+    Handle(Rc<RefCell<File>>),
+
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Lt,
+    Not,
+
+    Dup,
+    Drop,
+    Swap,
+    Over,
+
+    If,
+    Else,
+    Fi,
+
+    Jmp,
+    Jz,
+    Call,
+    Ret,
+    Halt,
+
+    Open,
+    Read,
+    Write,
+    Seek,
+    Close,
+    Print
+}
+
+impl PartialEq for Instr {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Instr::Num(a), Instr::Num(b)) => a == b,
+            (Instr::Bool(a), Instr::Bool(b)) => a == b,
+            (Instr::Text(a), Instr::Text(b)) => a == b,
+            (Instr::Mode(a), Instr::Mode(b)) => a == b,
+            (Instr::Whence(a), Instr::Whence(b)) => a == b,
+            (Instr::Handle(a), Instr::Handle(b)) => Rc::ptr_eq(a, b),
+            (a, b) => mem::discriminant(a) == mem::discriminant(b)
+        }
+    }
+}
+
+struct InstrVisitor;
+
+impl<'de> Visitor<'de> for InstrVisitor {
+    type Value = Instr;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Instr token")
+    }
+
+    // anything that isn't a number, boolean, keyword, mode or whence is text
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        let i = match v {
+            "true" => Instr::Bool(true),
+            "false" => Instr::Bool(false),
+            "+" => Instr::Add,
+            "-" => Instr::Sub,
+            "*" => Instr::Mul,
+            "/" => Instr::Div,
+            "%" => Instr::Rem,
+            "=" => Instr::Eq,
+            "<" => Instr::Lt,
+            "NOT" => Instr::Not,
+            "DUP" => Instr::Dup,
+            "DROP" => Instr::Drop,
+            "SWAP" => Instr::Swap,
+            "OVER" => Instr::Over,
+            "IF" => Instr::If,
+            "ELSE" => Instr::Else,
+            "FI" => Instr::Fi,
+            "JMP" => Instr::Jmp,
+            "JZ" => Instr::Jz,
+            "CALL" => Instr::Call,
+            "RET" => Instr::Ret,
+            "HALT" => Instr::Halt,
+            "OPEN" => Instr::Open,
+            "READ" => Instr::Read,
+            "WRITE" => Instr::Write,
+            "SEEK" => Instr::Seek,
+            "CLOSE" => Instr::Close,
+            "PRINT" => Instr::Print,
+            _ => {
+                if let Some(n) = literal::int(v) {
+                    Instr::Num(n)
+                } else if let Some(m) = literal::mode(v) {
+                    Instr::Mode(m)
+                } else if let Some(w) = literal::whence(v) {
+                    Instr::Whence(w)
+                } else {
+                    Instr::Text(v.to_string())
+                }
+            }
+        };
+        Ok(i)
+    }
+}
+
+impl<'de> Deserialize<'de> for Instr {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Instr, D::Error> {
+        d.deserialize_any(InstrVisitor)
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Num(n) => write!(f, "{}", n),
+            Instr::Bool(b) => write!(f, "{}", b),
+            // quoted if it would be read back as anything else
+            Instr::Text(s) => match InstrVisitor.visit_str::<de::value::Error>(s) {
+                Ok(Instr::Text(_)) if literal::bare(s) => write!(f, "{}", s),
+                _ => write!(f, "{}", literal::quote(s))
+            },
+            Instr::Mode(m) => write!(f, "{}", m),
+            Instr::Whence(w) => write!(f, "{}", w),
+            Instr::Handle(_) => write!(f, "<file>"),
+            Instr::Add => write!(f, "+"),
+            Instr::Sub => write!(f, "-"),
+            Instr::Mul => write!(f, "*"),
+            Instr::Div => write!(f, "/"),
+            Instr::Rem => write!(f, "%"),
+            Instr::Eq => write!(f, "="),
+            Instr::Lt => write!(f, "<"),
+            Instr::Not => write!(f, "NOT"),
+            Instr::Dup => write!(f, "DUP"),
+            Instr::Drop => write!(f, "DROP"),
+            Instr::Swap => write!(f, "SWAP"),
+            Instr::Over => write!(f, "OVER"),
+            Instr::If => write!(f, "IF"),
+            Instr::Else => write!(f, "ELSE"),
+            Instr::Fi => write!(f, "FI"),
+            Instr::Jmp => write!(f, "JMP"),
+            Instr::Jz => write!(f, "JZ"),
+            Instr::Call => write!(f, "CALL"),
+            Instr::Ret => write!(f, "RET"),
+            Instr::Halt => write!(f, "HALT"),
+            Instr::Open => write!(f, "OPEN"),
+            Instr::Read => write!(f, "READ"),
+            Instr::Write => write!(f, "WRITE"),
+            Instr::Seek => write!(f, "SEEK"),
+            Instr::Close => write!(f, "CLOSE"),
+            Instr::Print => write!(f, "PRINT")
+        }
+    }
+}
+
+impl Bytecode for Instr {
+    fn opcode(&self) -> u8 {
+        match self {
+            Instr::Num(_) => 0x01,
+            Instr::Bool(_) => 0x02,
+            Instr::Text(_) => 0x03,
+            Instr::Mode(_) => 0x04,
+            Instr::Whence(_) => 0x05,
+            Instr::Add => 0x10,
+            Instr::Sub => 0x11,
+            Instr::Mul => 0x12,
+            Instr::Div => 0x13,
+            Instr::Rem => 0x14,
+            Instr::Eq => 0x15,
+            Instr::Lt => 0x16,
+            Instr::Not => 0x17,
+            Instr::Dup => 0x20,
+            Instr::Drop => 0x21,
+            Instr::Swap => 0x22,
+            Instr::Over => 0x23,
+            Instr::If => 0x30,
+            Instr::Else => 0x31,
+            Instr::Fi => 0x32,
+            Instr::Jmp => 0x40,
+            Instr::Jz => 0x41,
+            Instr::Call => 0x42,
+            Instr::Ret => 0x43,
+            Instr::Halt => 0x44,
+            Instr::Open => 0x50,
+            Instr::Read => 0x51,
+            Instr::Write => 0x52,
+            Instr::Seek => 0x53,
+            Instr::Close => 0x54,
+            Instr::Print => 0x55,
+            // never decoded, open files only exist while running
+            Instr::Handle(_) => 0xff
+        }
+    }
+
+    fn encode(&self, e: &mut Encoder) {
+        match self {
+            Instr::Num(n) => e.signed(*n),
+            Instr::Bool(b) => e.u8(*b as u8),
+            Instr::Text(s) => e.str(s),
+            Instr::Mode(m) => e.str(&m.to_string()),
+            Instr::Whence(w) => e.str(&w.to_string()),
+            _ => {}
+        }
+    }
+
+    fn decode(opcode: u8, d: &mut Decoder) -> Result<Self, BytecodeError> {
+        let i = match opcode {
+            0x01 => Instr::Num(d.signed()?),
+            0x02 => match d.u8()? {
+                0 => Instr::Bool(false),
+                1 => Instr::Bool(true),
+                _ => return Err(d.invalid("bad boolean"))
+            },
+            0x03 => Instr::Text(d.str()?.to_string()),
+            0x04 => Instr::Mode(literal::mode(d.str()?).ok_or_else(|| d.invalid("bad file mode"))?),
+            0x05 => Instr::Whence(literal::whence(d.str()?).ok_or_else(|| d.invalid("bad whence"))?),
+            0x10 => Instr::Add,
+            0x11 => Instr::Sub,
+            0x12 => Instr::Mul,
+            0x13 => Instr::Div,
+            0x14 => Instr::Rem,
+            0x15 => Instr::Eq,
+            0x16 => Instr::Lt,
+            0x17 => Instr::Not,
+            0x20 => Instr::Dup,
+            0x21 => Instr::Drop,
+            0x22 => Instr::Swap,
+            0x23 => Instr::Over,
+            0x30 => Instr::If,
+            0x31 => Instr::Else,
+            0x32 => Instr::Fi,
+            0x40 => Instr::Jmp,
+            0x41 => Instr::Jz,
+            0x42 => Instr::Call,
+            0x43 => Instr::Ret,
+            0x44 => Instr::Halt,
+            0x50 => Instr::Open,
+            0x51 => Instr::Read,
+            0x52 => Instr::Write,
+            0x53 => Instr::Seek,
+            0x54 => Instr::Close,
+            0x55 => Instr::Print,
+            _ => return Err(d.unknown(opcode))
+        };
+        Ok(i)
+    }
+}
+
+fn pop(m: &mut Machine<Instr>) -> Result<Instr, MachineError<Instr>> {
+    m.pop().ok_or_else(|| Cause::StackUnderflow.into())
+}
+
+fn pop_num(m: &mut Machine<Instr>) -> Result<i64, MachineError<Instr>> {
+    match pop(m)? {
+        Instr::Num(n) => Ok(n),
+        _ => Err(Cause::TypeMismatch.into())
+    }
+}
+
+// jump targets are pushed as numbers, usually by a label reference
+fn pop_target(m: &mut Machine<Instr>) -> Result<usize, MachineError<Instr>> {
+    match pop_num(m)? {
+        n if n >= 0 => Ok(n as usize),
+        _ => Err(Cause::Other("negative jump target".to_string()).into())
+    }
+}
+
+// the numbers on top of the stack, the right hand side on top
+fn pop_nums(m: &mut Machine<Instr>) -> Result<(i64, i64), MachineError<Instr>> {
+    let r = pop_num(m)?;
+    let l = pop_num(m)?;
+    Ok((l, r))
+}
+
+fn arith(r: Option<i64>) -> Result<Instr, MachineError<Instr>> {
+    r.map(Instr::Num).ok_or_else(|| Cause::Other("arithmetic overflow or division by zero".to_string()).into())
+}
+
+impl Instruction<Instr> for Instr {
+    fn execute(&self, ip: usize, m: &mut Machine<Instr>, io: &dyn AppIO<Instr>) -> Result<(), MachineError<Instr>> {
+        match self {
+            Instr::Num(_) |
+            Instr::Bool(_) |
+            Instr::Text(_) |
+            Instr::Mode(_) |
+            Instr::Whence(_) => m.push(self.clone()),
+            Instr::Handle(_) => return Err(Cause::InvalidInstruction.into()),
+            Instr::Add => {
+                let (l, r) = pop_nums(m)?;
+                m.push(arith(l.checked_add(r))?);
+            },
+            Instr::Sub => {
+                let (l, r) = pop_nums(m)?;
+                m.push(arith(l.checked_sub(r))?);
+            },
+            Instr::Mul => {
+                let (l, r) = pop_nums(m)?;
+                m.push(arith(l.checked_mul(r))?);
+            },
+            Instr::Div => {
+                let (l, r) = pop_nums(m)?;
+                m.push(arith(l.checked_div(r))?);
+            },
+            Instr::Rem => {
+                let (l, r) = pop_nums(m)?;
+                m.push(arith(l.checked_rem(r))?);
+            },
+            Instr::Eq => {
+                let r = pop(m)?;
+                let l = pop(m)?;
+                m.push(Instr::Bool(l == r));
+            },
+            Instr::Lt => {
+                let (l, r) = pop_nums(m)?;
+                m.push(Instr::Bool(l < r));
+            },
+            Instr::Not => match pop(m)? {
+                Instr::Bool(b) => m.push(Instr::Bool(!b)),
+                _ => return Err(Cause::TypeMismatch.into())
+            },
+            Instr::Dup => {
+                let a = pop(m)?;
+                m.push(a.clone());
+                m.push(a);
+            },
+            Instr::Drop => {
+                pop(m)?;
+            },
+            Instr::Swap => {
+                let b = pop(m)?;
+                let a = pop(m)?;
+                m.push(b);
+                m.push(a);
+            },
+            Instr::Over => {
+                let b = pop(m)?;
+                let a = pop(m)?;
+                m.push(a.clone());
+                m.push(b);
+                m.push(a);
+            },
+            Instr::If => {
+                let b = match m.block(ip) {
+                    Some(b) => b,
+                    None => return Err(Cause::InvalidInstruction.into())
+                };
+                match (pop(m)?, b.split) {
+                    // run the first arm and come back after the FI
+                    (Instr::Bool(true), _) => m.call(ip + 1, b.close + 1),
+                    (Instr::Bool(false), Some(s)) => m.call(s + 1, b.close + 1),
+                    (Instr::Bool(false), None) => m.jump(b.close + 1),
+                    _ => return Err(Cause::TypeMismatch.into())
+                }
+                return Ok(());
+            },
+            // the end of whichever arm ran
+            Instr::Else | Instr::Fi => return m.ret(),
+            Instr::Jmp => {
+                let t = pop_target(m)?;
+                m.jump(t);
+                return Ok(());
+            },
+            // jumps when the value under the target is false or zero
+            Instr::Jz => {
+                let t = pop_target(m)?;
+                match pop(m)? {
+                    Instr::Bool(false) | Instr::Num(0) => m.jump(t),
+                    Instr::Bool(_) | Instr::Num(_) => m.next(ip),
+                    _ => return Err(Cause::TypeMismatch.into())
+                }
+                return Ok(());
+            },
+            Instr::Call => {
+                let t = pop_target(m)?;
+                m.call(t, ip + 1);
+                return Ok(());
+            },
+            Instr::Ret => return m.ret(),
+            Instr::Halt => {
+                m.halt();
+                return Ok(());
+            },
+            Instr::Open => io.open(m)?,
+            Instr::Read => io.read(m)?,
+            Instr::Write => io.write(m)?,
+            Instr::Seek => io.seek(m)?,
+            Instr::Close => io.close(m)?,
+            Instr::Print => io.print(m)?
+        }
+        m.next(ip);
+        Ok(())
+    }
+
+    fn block(&self) -> Option<Block> {
+        match self {
+            Instr::If => Some(Block::Open),
+            Instr::Else => Some(Block::Split),
+            Instr::Fi => Some(Block::Close),
+            _ => None
+        }
+    }
+
+    fn flow(&self) -> Flow {
+        match self {
+            Instr::Jmp | Instr::Jz | Instr::Call => Flow::Dynamic,
+            Instr::Ret => Flow::Return,
+            Instr::Halt => Flow::Halt,
+            _ => Flow::Next
+        }
+    }
+
+    // anything in quotes is text
+    fn quoted(text: &str) -> Option<Instr> {
+        Some(Instr::Text(text.to_string()))
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn pop_handle(m: &mut Machine<Instr>) -> io::Result<Rc<RefCell<File>>> {
+    match m.pop() {
+        Some(Instr::Handle(h)) => Ok(h),
+        _ => Err(invalid("no open file"))
+    }
+}
+
+// io on the real filesystem, with PRINT going to an output of its own:
+//
+//   path mode OPEN -> file
+//   file n READ -> file text
+//   file text WRITE -> file
+//   file offset whence SEEK -> file
+//   file CLOSE ->
+//   value PRINT ->
+pub struct FileIO<W: Write = io::Sink> {
+    out: RefCell<W>
+}
+
+impl FileIO {
+    // throws away whatever is printed
+    pub fn new() -> Self {
+        Self::with_output(io::sink())
+    }
+}
+
+impl<W: Write> FileIO<W> {
+    pub fn with_output(out: W) -> Self {
+        FileIO { out: RefCell::new(out) }
+    }
+
+    pub fn into_output(self) -> W {
+        self.out.into_inner()
+    }
+}
+
+impl Default for FileIO {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Write> AppIO<Instr> for FileIO<W> {
+    fn open(&self, m: &mut Machine<Instr>) -> io::Result<()> {
+        let mode = match m.pop() {
+            Some(Instr::Mode(mode)) => mode,
+            _ => return Err(invalid("no file mode"))
+        };
+        let path = match m.pop() {
+            Some(Instr::Text(p)) => p,
+            _ => return Err(invalid("no file path"))
+        };
+        let f = OpenOptions::new()
+            .read(mode.read)
+            .write(mode.write || mode.plus)
+            .append(mode.append)
+            .create(mode.write || mode.append)
+            .truncate(mode.write && !mode.append)
+            .open(&path)?;
+        m.push(Instr::Handle(Rc::new(RefCell::new(f))));
+        Ok(())
+    }
+
+    fn read(&self, m: &mut Machine<Instr>) -> io::Result<()> {
+        let n = match m.pop() {
+            Some(Instr::Num(n)) if n >= 0 => n as u64,
+            _ => return Err(invalid("no length to read"))
+        };
+        let h = pop_handle(m)?;
+        let mut buf = Vec::new();
+        (&*h.borrow()).take(n).read_to_end(&mut buf)?;
+        m.push(Instr::Handle(h));
+        m.push(Instr::Text(String::from_utf8_lossy(&buf).into_owned()));
+        Ok(())
+    }
+
+    fn write(&self, m: &mut Machine<Instr>) -> io::Result<()> {
+        let s = match m.pop() {
+            Some(Instr::Text(s)) => s,
+            Some(Instr::Handle(_)) | None => return Err(invalid("no data to write")),
+            Some(i) => i.to_string()
+        };
+        let h = pop_handle(m)?;
+        h.borrow_mut().write_all(s.as_bytes())?;
+        m.push(Instr::Handle(h));
+        Ok(())
+    }
+
+    fn seek(&self, m: &mut Machine<Instr>) -> io::Result<()> {
+        let w = match m.pop() {
+            Some(Instr::Whence(w)) => w,
+            _ => return Err(invalid("no whence"))
+        };
+        let n = match m.pop() {
+            Some(Instr::Num(n)) => n,
+            _ => return Err(invalid("no offset"))
+        };
+        let h = pop_handle(m)?;
+        let pos = match w {
+            Whence::Start if n >= 0 => SeekFrom::Start(n as u64),
+            Whence::Start => return Err(invalid("negative offset from the start")),
+            Whence::Cur => SeekFrom::Current(n),
+            Whence::End => SeekFrom::End(n)
+        };
+        h.borrow_mut().seek(pos)?;
+        m.push(Instr::Handle(h));
+        Ok(())
+    }
+
+    // the file closes when the last copy of its handle goes
+    fn close(&self, m: &mut Machine<Instr>) -> io::Result<()> {
+        pop_handle(m).map(|_| ())
+    }
+
+    // text is printed as it is, without the quotes a script would need
+    fn print(&self, m: &mut Machine<Instr>) -> io::Result<()> {
+        let mut out = self.out.borrow_mut();
+        match m.pop() {
+            Some(Instr::Text(s)) => writeln!(out, "{}", s),
+            Some(i) => writeln!(out, "{}", i),
+            None => Err(invalid("nothing to print"))
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{
+    AppIO,
+    Instruction,
+    Machine,
+    MachineBuilder,
+    MachineError,
+    ParseError,
+    Script,
+    Stack
+};
+use serde::de::DeserializeOwned;
+use std::{
+    error,
+    fmt,
+    fs,
+    io::{
+        self,
+        BufRead,
+        Write
+    }
+};
+
+pub const HELP: &str = "\
+anything that isn't a command is run as a script on the same stack
+.stack       show the stack, top first
+.undo        go back to the stack before the last line
+.reset       clear the stack and the undo history
+.load FILE   run the script in FILE
+.help        show this message
+.quit        leave";
+
+#[derive(Clone, Debug)]
+pub enum Reply<I: Clone> {
+    // the stack after the line, top first when displayed
+    Stack(Stack<I>),
+    Help(&'static str),
+    Quit
+}
+
+#[derive(Debug)]
+pub enum ReplError<I: Clone> {
+    Parse(ParseError),
+    Machine(MachineError<I>),
+    Io(io::Error),
+    NothingToUndo,
+    Usage(String)
+}
+
+impl<I: Clone + fmt::Display> fmt::Display for ReplError<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplError::Parse(e) => write!(f, "{}", e),
+            ReplError::Machine(e) => write!(f, "{}", e),
+            ReplError::Io(e) => write!(f, "{}", e),
+            ReplError::NothingToUndo => write!(f, "nothing to undo"),
+            ReplError::Usage(s) => write!(f, "{}", s)
+        }
+    }
+}
+
+impl<I: Clone + fmt::Debug + fmt::Display + 'static> error::Error for ReplError<I> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ReplError::Parse(e) => Some(e),
+            ReplError::Machine(e) => Some(e),
+            ReplError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+// runs one line at a time against the same machine so that what each line
+// leaves on the data stack is there for the next; a line that fails leaves
+// the stack as it was before it
+pub struct Repl<I: Clone> {
+    m: Machine<I>,
+    // the stack before each line that ran, for undo
+    history: Vec<Stack<I>>
+}
+
+impl<I: Clone + Instruction<I> + DeserializeOwned> Repl<I> {
+    // the machine's limits, tracer and clock apply to every line
+    pub fn new(m: Machine<I>) -> Self {
+        Repl {
+            m,
+            history: Vec::new()
+        }
+    }
+
+    pub fn machine(&self) -> &Machine<I> {
+        &self.m
+    }
+
+    pub fn stack(&self) -> &Stack<I> {
+        self.m.stack()
+    }
+
+    // runs a command or a script fragment
+    pub fn eval(&mut self, line: &str, io: &dyn AppIO<I>) -> Result<Reply<I>, ReplError<I>> {
+        let t = line.trim();
+        let (cmd, arg) = match t.find(char::is_whitespace) {
+            Some(n) => (&t[..n], t[n..].trim()),
+            None => (t, "")
+        };
+        match (cmd, arg) {
+            (".stack", "") => Ok(Reply::Stack(self.stack().clone())),
+            (".undo", "") => {
+                self.undo()?;
+                Ok(Reply::Stack(self.stack().clone()))
+            },
+            (".reset", "") => {
+                self.reset();
+                Ok(Reply::Stack(self.stack().clone()))
+            },
+            (".load", "") => Err(ReplError::Usage(".load wants a file".to_string())),
+            (".load", path) => self.load(path, io).map(Reply::Stack),
+            (".help", "") => Ok(Reply::Help(HELP)),
+            (".quit", "") | (".exit", "") => Ok(Reply::Quit),
+            _ => {
+                let s = Script::parse(line).map_err(ReplError::Parse)?;
+                self.run(&s, io).map(Reply::Stack)
+            }
+        }
+    }
+
+    pub fn run(&mut self, s: &Script<I>, io: &dyn AppIO<I>) -> Result<Stack<I>, ReplError<I>> {
+        let before = self.stack().clone();
+        self.m.load(s);
+        match self.m.execute(io) {
+            Ok(d) => {
+                self.history.push(before);
+                Ok(d)
+            },
+            Err(e) => {
+                self.m.set_stack(before);
+                Err(ReplError::Machine(e))
+            }
+        }
+    }
+
+    // runs the script in a file like one line
+    pub fn load(&mut self, path: &str, io: &dyn AppIO<I>) -> Result<Stack<I>, ReplError<I>> {
+        let text = fs::read_to_string(path).map_err(ReplError::Io)?;
+        let s = Script::parse(&text).map_err(ReplError::Parse)?;
+        self.run(&s, io)
+    }
+
+    pub fn undo(&mut self) -> Result<(), ReplError<I>> {
+        match self.history.pop() {
+            Some(d) => {
+                self.m.set_stack(d);
+                Ok(())
+            },
+            None => Err(ReplError::NothingToUndo)
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.m.reset();
+        self.history.clear();
+    }
+}
+
+impl<I: Clone + Instruction<I> + DeserializeOwned + fmt::Display> Repl<I> {
+    // reads lines from input until it ends or .quit, writing a prompt before
+    // each one and the stack or the error after
+    pub fn interact<R: BufRead, W: Write>(&mut self, input: R, mut output: W, io: &dyn AppIO<I>) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            write!(output, "> ")?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(l) => l?,
+                None => break
+            };
+            match self.eval(&line, io) {
+                Ok(Reply::Stack(d)) => write!(output, "{}", d)?,
+                Ok(Reply::Help(h)) => writeln!(output, "{}", h)?,
+                Ok(Reply::Quit) => return Ok(()),
+                Err(e) => writeln!(output, "error: {}", e)?
+            }
+        }
+        writeln!(output)
+    }
+}
+
+impl<I: Clone + Instruction<I> + DeserializeOwned> Default for Repl<I> {
+    fn default() -> Self {
+        Self::new(MachineBuilder::new().build())
+    }
+}
+
+impl<I: Clone + Instruction<I> + DeserializeOwned> From<Machine<I>> for Repl<I> {
+    fn from(m: Machine<I>) -> Self {
+        Self::new(m)
+    }
+}
@@ -1,6 +1,22 @@
+use crate::{
+    bytecode::{
+        self,
+        Bytecode,
+        BytecodeError,
+        Decoder,
+        Encoder
+    },
+    Block,
+    BlockError,
+    BlockTable,
+    Flow,
+    Instruction,
+    token
+};
 use serde::{
     de::{
         self,
+        DeserializeOwned,
         IntoDeserializer
     },
     Deserialize,
@@ -10,41 +26,227 @@ use serde::{
 };
 use std::{
     clone::Clone,
+    cmp,
+    collections::BTreeMap,
     convert::From,
+    error,
     fmt,
     marker::PhantomData,
     vec::Vec
 };
 
 #[derive(Clone, Debug, PartialEq)]
-pub struct Script<I: Clone>(Vec<I>);
+pub struct Script<I: Clone> {
+    code: Vec<I>,
+    blocks: Result<BlockTable, BlockError>,
+    labels: BTreeMap<String, usize>
+}
 
 impl<I: Clone> Script<I> {
     pub fn new() -> Self {
-        Script(vec![])
+        Script {
+            code: vec![],
+            blocks: Ok(BlockTable::default()),
+            labels: BTreeMap::new()
+        }
     }
 
     pub fn get(&self, l: usize) -> Option<I> {
-        if let Some(i) = self.0.get(l) {
+        if let Some(i) = self.code.get(l) {
             return Some(i.clone());
         }
         None
     }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &I> {
+        self.code.iter()
+    }
+
+    // the block table worked out when the script was loaded, or what was
+    // wrong with the nesting of its blocks
+    pub fn blocks(&self) -> Result<&BlockTable, &BlockError> {
+        self.blocks.as_ref()
+    }
+
+    // names the instruction at index, which may be one past the end
+    pub fn add_label(&mut self, name: &str, index: usize) -> Result<(), LabelError> {
+        if !is_label(name) {
+            return Err(LabelError(name.to_string()));
+        }
+        self.labels.insert(name.to_string(), index);
+        Ok(())
+    }
+
+    // replaces the labels with ones already known to be good
+    pub(crate) fn set_labels(&mut self, labels: BTreeMap<String, usize>) {
+        self.labels = labels;
+    }
+
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).copied()
+    }
+
+    pub fn labels(&self) -> &BTreeMap<String, usize> {
+        &self.labels
+    }
+
+    // the labels naming the instruction at index
+    fn labels_at(&self, index: usize) -> impl Iterator<Item = &String> {
+        self.labels.iter().filter(move |(_, i)| **i == index).map(|(n, _)| n)
+    }
+}
+
+impl<I: Clone + Instruction<I>> Script<I> {
+    // the flow of the instruction at ip, with structured blocks taken to work
+    // like IF/ELSE/FI: the opening instruction goes on or past the split or
+    // close, the split goes past the close and the close goes on
+    pub fn flow(&self, ip: usize) -> Option<Flow> {
+        let i = self.code.get(ip)?;
+        let info = self.blocks.as_ref().ok().and_then(|b| b.get(ip));
+        match (i.block(), info) {
+            (Some(Block::Open), Some(b)) => Some(Flow::Branch(b.split.unwrap_or(b.close) + 1)),
+            (Some(Block::Split), Some(b)) => Some(Flow::Jump(b.close + 1)),
+            (Some(Block::Close), Some(_)) => Some(Flow::Next),
+            _ => Some(i.flow())
+        }
+    }
+}
+
+impl<I: Clone + Bytecode + Instruction<I>> Script<I> {
+    // the header, the instructions as opcodes and operands, then the labels
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        let mut e = Encoder::new();
+        for b in &bytecode::MAGIC {
+            e.u8(*b);
+        }
+        e.u8(bytecode::VERSION);
+        e.varint(self.code.len() as u64);
+        for i in &self.code {
+            e.u8(i.opcode());
+            i.encode(&mut e);
+        }
+        e.varint(self.labels.len() as u64);
+        for (name, index) in &self.labels {
+            e.str(name);
+            e.varint(*index as u64);
+        }
+        e.into_inner()
+    }
+
+    pub fn from_bytecode(b: &[u8]) -> Result<Self, BytecodeError> {
+        let mut d = Decoder::new(b);
+        for m in &bytecode::MAGIC {
+            if d.u8().ok() != Some(*m) {
+                return Err(BytecodeError::BadMagic);
+            }
+        }
+        let version = d.u8()?;
+        if version != bytecode::VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+
+        // every instruction is at least one byte
+        let n = d.len(d.remaining())?;
+        let mut code = Vec::with_capacity(n);
+        for _ in 0..n {
+            let offset = d.offset();
+            let op = d.u8()?;
+            match I::decode(op, &mut d) {
+                Ok(i) => code.push(i),
+                Err(BytecodeError::UnknownOpcode { opcode, .. }) => {
+                    return Err(BytecodeError::UnknownOpcode { offset, opcode });
+                },
+                Err(e) => return Err(e)
+            }
+        }
+
+        let mut labels = BTreeMap::new();
+        let n = d.len(d.remaining())?;
+        for _ in 0..n {
+            let name = d.str()?;
+            let index = d.len(code.len())
+                .map_err(|_| BytecodeError::BadLabel(name.to_string()))?;
+            if !is_label(name) || labels.insert(name.to_string(), index).is_some() {
+                return Err(BytecodeError::BadLabel(name.to_string()));
+            }
+        }
+
+        if d.remaining() > 0 {
+            return Err(BytecodeError::TrailingBytes(d.offset()));
+        }
+
+        let mut script = Script::from(code);
+        script.labels = labels;
+        if let Err(e) = script.blocks() {
+            return Err(BytecodeError::Block(e.clone()));
+        }
+        Ok(script)
+    }
+}
+
+impl<I: Clone> Default for Script<I> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<I: Clone> From<Vec<I>> for Script<I> {
+impl<I: Clone + Instruction<I>> From<Vec<I>> for Script<I> {
     fn from(s: Vec<I>) -> Self {
-        Script(s)
+        let blocks = BlockTable::build(&s);
+        Script {
+            code: s,
+            blocks,
+            labels: BTreeMap::new()
+        }
     }
 }
 
 impl<I: Clone + fmt::Display> fmt::Display for Script<I> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (n, i) in self.0.iter().enumerate() {
-            if n > 0 {
-                write!(f, " ").unwrap();
+        if f.alternate() {
+            return self.fmt_pretty(f);
+        }
+        let mut sep = "";
+        for n in 0..=self.code.len() {
+            for l in self.labels_at(n) {
+                write!(f, "{}:{}", sep, l)?;
+                sep = " ";
+            }
+            if let Some(i) = self.code.get(n) {
+                write!(f, "{}{}", sep, token::quote(&i.to_string()))?;
+                sep = " ";
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: Clone + fmt::Display> Script<I> {
+    // one instruction or label per line, indented by block depth
+    fn fmt_pretty(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sep = "";
+        for n in 0..=self.code.len() {
+            let depth = match &self.blocks {
+                Ok(b) => b.depth(n),
+                Err(_) => 0
+            };
+            let indent = "    ".repeat(depth);
+            for l in self.labels_at(n) {
+                write!(f, "{}{}:{}", sep, indent, l)?;
+                sep = "\n";
+            }
+            if let Some(i) = self.code.get(n) {
+                write!(f, "{}{}{}", sep, indent, token::quote(&i.to_string()))?;
+                sep = "\n";
             }
-            write!(f, "{}", i).unwrap();
         }
         Ok(())
     }
@@ -57,30 +259,225 @@ impl<I: Clone + fmt::Display> Serialize for Script<I> {
     }
 }
 
+// a label has to read back from the script text format as the same name, so
+// it can't be empty or hold whitespace or quotes
+fn is_label(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(|c| c.is_whitespace() || c == '"')
+}
+
+// a name that add_label won't take
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelError(pub String);
+
+impl fmt::Display for LabelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' can't be a label", self.0)
+    }
+}
+
+impl error::Error for LabelError {}
+
+// where and why a script failed to parse; the index is the token's position
+// in the script, counting labels, and is None when the text couldn't be split
+// into tokens at all
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub index: Option<usize>,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+    pub msg: String
+}
+
+impl ParseError {
+    fn new(s: &str, index: Option<usize>, offset: usize, token: &str, msg: String) -> Self {
+        // lines and columns count from 1, columns in characters
+        let before = &s[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = match before.rfind('\n') {
+            Some(n) => before[n + 1..].chars().count() + 1,
+            None => before.chars().count() + 1
+        };
+        ParseError {
+            index,
+            offset,
+            line,
+            column,
+            token: token.to_string(),
+            msg
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.msg)?;
+        if let Some(i) = self.index {
+            write!(f, " (token {} '{}')", i, self.token)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl<I: Clone + DeserializeOwned + Instruction<I>> Script<I> {
+    // parses the script text format, stopping at the first error
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        parse(s, false).map_err(|mut e| e.remove(0))
+    }
+
+    // parses the script text format, reporting every bad token
+    pub fn parse_all(s: &str) -> Result<Self, Vec<ParseError>> {
+        parse(s, true)
+    }
+}
+
+fn parse<'de, I: Clone + Deserialize<'de> + Instruction<I>>(s: &str, all: bool) -> Result<Script<I>, Vec<ParseError>> {
+    let tokens = match token::tokenize(s) {
+        Ok(t) => t,
+        Err(e) => {
+            let end = s[e.offset..].find(char::is_whitespace).map_or(s.len(), |n| e.offset + n);
+            return Err(vec![ParseError::new(s, None, e.offset, &s[e.offset..end], e.msg)]);
+        }
+    };
+    let bad = |n: usize, msg: String| ParseError::new(s, Some(n), tokens[n].offset, &tokens[n].text, msg);
+    let mut errors = Vec::new();
+
+    // ':name' names the next instruction so find them all first to allow
+    // jumping forward
+    let mut labels = BTreeMap::new();
+    let mut n = 0;
+    for (ti, t) in tokens.iter().enumerate() {
+        if let Some(l) = t.label() {
+            if l.is_empty() {
+                errors.push(bad(ti, "empty label".to_string()));
+            } else if labels.insert(l.to_string(), n).is_some() {
+                errors.push(bad(ti, format!("label '{}' defined twice", l)));
+            }
+        } else {
+            n += 1;
+        }
+        if !all && !errors.is_empty() {
+            return Err(errors);
+        }
+    }
+
+    // '@name' is replaced with the index of the instruction it names and a
+    // quoted token goes to Instruction::quoted before being read as usual; the
+    // token each instruction came from is kept for reporting block errors
+    let mut v: Vec<I> = Vec::new();
+    let mut from = Vec::new();
+    for (ti, t) in tokens.iter().enumerate() {
+        if t.label().is_some() {
+            continue;
+        }
+        let i: Result<I, de::value::Error> = match t.reference() {
+            Some(l) => match labels.get(l) {
+                Some(n) => Deserialize::deserialize(n.to_string().into_deserializer()),
+                None => Err(de::Error::custom(format!("unknown label '{}'", l)))
+            },
+            None if t.quoted => match I::quoted(&t.text) {
+                Some(i) => Ok(i),
+                None => Deserialize::deserialize(t.text.as_ref().into_deserializer())
+            },
+            None => Deserialize::deserialize(t.text.as_ref().into_deserializer())
+        };
+        match i {
+            Ok(i) => {
+                v.push(i);
+                from.push(ti);
+            },
+            Err(e) => errors.push(bad(ti, e.to_string()))
+        }
+        if !all && !errors.is_empty() {
+            return Err(errors);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // reject badly nested blocks before anything tries to run them
+    let mut script = Script::from(v);
+    script.labels = labels;
+    if let Err(e) = script.blocks() {
+        return Err(vec![bad(from[e.index()], e.to_string())]);
+    }
+    Ok(script)
+}
+
 struct ScriptVisitor<I>(PhantomData<fn() -> I>);
 
-impl<'de, I: Clone + Deserialize<'de> + fmt::Debug> de::Visitor<'de> for ScriptVisitor<I> {
+impl<'de, I: Clone + Deserialize<'de> + fmt::Debug + Instruction<I>> de::Visitor<'de> for ScriptVisitor<I> {
     type Value = Script<I>;
 
     fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Script string")
+        write!(f, "Script string or sequence")
     }
 
     fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
+        parse(s, false).map_err(|e| E::custom(&e[0]))
+    }
 
-        let mut v: Vec<I> = Vec::new();
-        for t in s.split_whitespace() {
-            let i: I = Deserialize::deserialize(t.into_deserializer())?;
+    // a sequence holds one instruction per element and has no labels. the
+    // length comes from the input so don't trust it with the allocation
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut v: Vec<I> = Vec::with_capacity(cmp::min(seq.size_hint().unwrap_or(0), 4096));
+        while let Some(i) = seq.next_element()? {
             v.push(i);
         }
-        Ok(Script::from(v))
+
+        let script = Script::from(v);
+        if let Err(e) = script.blocks() {
+            return Err(de::Error::custom(e));
+        }
+        Ok(script)
     }
 }
 
-impl<'de, I: Clone + Deserialize<'de> + fmt::Debug> Deserialize<'de> for Script<I> {
+impl<'de, I: Clone + Deserialize<'de> + fmt::Debug + Instruction<I>> Deserialize<'de> for Script<I> {
     fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Script<I>, D::Error>
     {
         d.deserialize_str(ScriptVisitor(PhantomData))
     }
 }
 
+// reads a script from either a string or a sequence, for formats that can
+// tell which one they hold, and writes it as a string; use it with
+// #[serde(with = "gsm::script::any")]
+pub mod any {
+    use super::*;
+
+    pub fn serialize<I: Clone + fmt::Display, S: Serializer>(script: &Script<I>, s: S) -> Result<S::Ok, S::Error> {
+        script.serialize(s)
+    }
+
+    pub fn deserialize<'de, I, D>(d: D) -> Result<Script<I>, D::Error>
+    where
+        I: Clone + Deserialize<'de> + fmt::Debug + Instruction<I>,
+        D: Deserializer<'de>
+    {
+        d.deserialize_any(ScriptVisitor(PhantomData))
+    }
+}
+
+// writes a script as a sequence of instructions instead of a string, for
+// instruction sets that serialize as maps or tagged enums; use it with
+// #[serde(with = "gsm::script::seq")]
+pub mod seq {
+    use super::*;
+
+    pub fn serialize<I: Clone + Serialize, S: Serializer>(script: &Script<I>, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(script.iter())
+    }
+
+    pub fn deserialize<'de, I, D>(d: D) -> Result<Script<I>, D::Error>
+    where
+        I: Clone + Deserialize<'de> + fmt::Debug + Instruction<I>,
+        D: Deserializer<'de>
+    {
+        d.deserialize_seq(ScriptVisitor(PhantomData))
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{
+    Instruction,
+    Script,
+    Stack
+};
+use semver::VersionReq;
+use serde::{
+    de,
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer
+};
+use std::{
+    clone::Clone,
+    fmt
+};
+
+// the state of a paused machine; the limits, tracer and other settings from
+// the MachineBuilder are not part of it, only the fuel used so far
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "I: Serialize + fmt::Display",
+    deserialize = "I: Deserialize<'de> + fmt::Debug + Instruction<I>"
+))]
+pub struct MachineSnapshot<I: Clone> {
+    #[serde(with = "version_req")]
+    pub version_req: VersionReq,
+    pub stack: Stack<I>,
+    pub rstack: Stack<usize>,
+    pub script: Script<I>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub consumed: u64,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub halted: bool
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+impl<I: Clone + Serialize + fmt::Display> MachineSnapshot<I> {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    pub fn to_cbor(&self) -> serde_cbor::Result<Vec<u8>> {
+        serde_cbor::to_vec(self)
+    }
+}
+
+impl<'de, I: Clone + Deserialize<'de> + fmt::Debug + Instruction<I>> MachineSnapshot<I> {
+    pub fn from_json(s: &'de str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+
+    pub fn from_cbor(b: &'de [u8]) -> serde_cbor::Result<Self> {
+        serde_cbor::from_slice(b)
+    }
+}
+
+mod version_req {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(v: &VersionReq, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(v)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<VersionReq, D::Error> {
+        let s = String::deserialize(d)?;
+        VersionReq::parse(&s).map_err(de::Error::custom)
+    }
+}
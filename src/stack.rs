@@ -1,3 +1,7 @@
+use serde::{
+    Deserialize,
+    Serialize
+};
 use std::{
     clone::Clone,
     convert::From,
@@ -5,7 +9,7 @@ use std::{
     vec::Vec
 };
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Stack<T: Clone>(Vec<T>);
 
 impl<T: Clone> Stack<T> {
@@ -28,6 +32,17 @@ impl<T: Clone> Stack<T> {
     pub fn size(&self) -> usize {
         self.0.len()
     }
+
+    // iterates from the bottom of the stack to the top
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.0.iter()
+    }
+}
+
+impl<T: Clone> Default for Stack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<I: Clone> From<Vec<I>> for Stack<I> {
@@ -38,8 +53,6 @@ impl<I: Clone> From<Vec<I>> for Stack<I> {
 
 impl<T: Clone + fmt::Display> fmt::Display for Stack<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.0.iter().rev().fold(Ok(()), |r, i| {
-            r.and_then(|_| writeln!(f, "{}", i))
-        })
+        self.0.iter().rev().try_for_each(|i| writeln!(f, "{}", i))
     }
 }
@@ -0,0 +1,182 @@
+use std::{
+    borrow::Cow,
+    fmt
+};
+
+// a token from the script text format, quoted tokens have had their quotes
+// removed and their escapes replaced
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Token<'a> {
+    pub text: Cow<'a, str>,
+    pub quoted: bool,
+    pub offset: usize
+}
+
+impl<'a> Token<'a> {
+    // a bare ':name' token
+    pub fn label(&self) -> Option<&str> {
+        if self.quoted { None } else { self.text.strip_prefix(':') }
+    }
+
+    // a bare '@name' token
+    pub fn reference(&self) -> Option<&str> {
+        if self.quoted { None } else { self.text.strip_prefix('@') }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TokenError {
+    pub offset: usize,
+    pub msg: String
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.msg, self.offset)
+    }
+}
+
+pub(crate) fn tokenize(s: &str) -> Result<Vec<Token<'_>>, TokenError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            // a line comment runs to the end of the line
+            for (_, c) in chars.by_ref() {
+                if c == '\n' {
+                    break;
+                }
+            }
+        } else if s[start..].starts_with("/*") {
+            // a block comment runs to the next '*/'
+            match s[start + 2..].find("*/") {
+                Some(o) => {
+                    let end = start + 2 + o + 2;
+                    while chars.peek().is_some_and(|&(o, _)| o < end) {
+                        chars.next();
+                    }
+                },
+                None => return Err(TokenError {
+                    offset: start,
+                    msg: "unterminated comment".to_string()
+                })
+            }
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((o, '\\')) => text.push(unescape(&mut chars, o)?),
+                    Some((_, c)) => text.push(c),
+                    None => return Err(TokenError {
+                        offset: start,
+                        msg: "unterminated string".to_string()
+                    })
+                }
+            }
+            if let Some(&(o, c)) = chars.peek() {
+                if !c.is_whitespace() {
+                    return Err(TokenError {
+                        offset: o,
+                        msg: "expected whitespace after closing quote".to_string()
+                    });
+                }
+            }
+            tokens.push(Token { text: Cow::Owned(text), quoted: true, offset: start });
+        } else {
+            let mut end = s.len();
+            while let Some(&(o, c)) = chars.peek() {
+                if c.is_whitespace() {
+                    end = o;
+                    break;
+                }
+                if c == '"' {
+                    return Err(TokenError {
+                        offset: o,
+                        msg: "unexpected quote".to_string()
+                    });
+                }
+                chars.next();
+            }
+            tokens.push(Token { text: Cow::Borrowed(&s[start..end]), quoted: false, offset: start });
+        }
+    }
+    Ok(tokens)
+}
+
+fn unescape<T: Iterator<Item = (usize, char)>>(chars: &mut T, offset: usize) -> Result<char, TokenError> {
+    let bad = |msg: &str| TokenError { offset, msg: msg.to_string() };
+    match chars.next() {
+        Some((_, '"')) => Ok('"'),
+        Some((_, '\\')) => Ok('\\'),
+        Some((_, 'n')) => Ok('\n'),
+        Some((_, 'r')) => Ok('\r'),
+        Some((_, 't')) => Ok('\t'),
+        Some((_, '0')) => Ok('\0'),
+        Some((_, 'u')) => {
+            // \u{...} with one to six hex digits
+            if chars.next().map(|(_, c)| c) != Some('{') {
+                return Err(bad("expected '{' in unicode escape"));
+            }
+            let mut hex = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '}')) => break,
+                    Some((_, c)) if c.is_ascii_hexdigit() && hex.len() < 6 => hex.push(c),
+                    _ => return Err(bad("bad unicode escape"))
+                }
+            }
+            u32::from_str_radix(&hex, 16).ok()
+                .and_then(std::char::from_u32)
+                .ok_or_else(|| bad("bad unicode escape"))
+        },
+        _ => Err(bad("unknown escape"))
+    }
+}
+
+// whether a token has to be quoted to come back as the same text
+pub(crate) fn needs_quotes(s: &str) -> bool {
+    s.is_empty() ||
+    s.starts_with(':') ||
+    s.starts_with('@') ||
+    s.starts_with('#') ||
+    s.starts_with("/*") ||
+    s.chars().any(|c| c.is_whitespace() || c.is_control() || c == '"' || c == '\\')
+}
+
+// an instruction written as a token, leaving it alone if the instruction set
+// has quoted it already
+pub(crate) fn quote(s: &str) -> Cow<'_, str> {
+    if needs_quotes(s) && !is_quoted(s) { Cow::Owned(quote_always(s)) } else { Cow::Borrowed(s) }
+}
+
+pub(crate) fn quote_always(s: &str) -> String {
+    let mut q = String::with_capacity(s.len() + 2);
+    q.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => q.push_str("\\\""),
+            '\\' => q.push_str("\\\\"),
+            '\n' => q.push_str("\\n"),
+            '\r' => q.push_str("\\r"),
+            '\t' => q.push_str("\\t"),
+            '\0' => q.push_str("\\0"),
+            c if c.is_control() => q.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => q.push(c)
+        }
+    }
+    q.push('"');
+    q
+}
+
+// whether s is one quoted token written the way quote_always writes it
+pub(crate) fn is_quoted(s: &str) -> bool {
+    match tokenize(s).as_deref() {
+        Ok([t]) => t.quoted && quote_always(&t.text) == s,
+        _ => false
+    }
+}
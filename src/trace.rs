@@ -0,0 +1,170 @@
+use crate::{
+    AppIO,
+    Instruction,
+    Machine,
+    Stack
+};
+use serde_json::{
+    json,
+    Value
+};
+use std::{
+    cell::RefCell,
+    clone::Clone,
+    fmt,
+    io::{
+        self,
+        Write
+    },
+    rc::Rc
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoOp {
+    Open,
+    Read,
+    Write,
+    Seek,
+    Close,
+    Print
+}
+
+impl fmt::Display for IoOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IoOp::Open => write!(f, "open"),
+            IoOp::Read => write!(f, "read"),
+            IoOp::Write => write!(f, "write"),
+            IoOp::Seek => write!(f, "seek"),
+            IoOp::Close => write!(f, "close"),
+            IoOp::Print => write!(f, "print")
+        }
+    }
+}
+
+// the return stack passed to `before` no longer holds the ip of the
+// instruction being executed, the one passed to `after` holds whatever the
+// instruction pushed for the machine to do next
+pub trait Tracer<I: Clone> {
+    fn before(&mut self, _ip: usize, _instr: &I, _d: &Stack<I>, _r: &Stack<usize>) {}
+    fn after(&mut self, _ip: usize, _instr: &I, _d: &Stack<I>, _r: &Stack<usize>) {}
+    fn io(&mut self, _op: IoOp, _result: &io::Result<()>, _d: &Stack<I>) {}
+}
+
+// wraps the AppIO handed to an instruction so that the tracer sees every call
+pub(crate) struct TracedIO<'a, I: Clone> {
+    pub(crate) io: &'a dyn AppIO<I>,
+    pub(crate) tracer: Rc<RefCell<dyn Tracer<I>>>
+}
+
+impl<'a, I: Clone + Instruction<I>> TracedIO<'a, I> {
+    fn trace(&self, op: IoOp, r: io::Result<()>, m: &Machine<I>) -> io::Result<()> {
+        self.tracer.borrow_mut().io(op, &r, m.stack());
+        r
+    }
+}
+
+impl<'a, I: Clone + Instruction<I>> AppIO<I> for TracedIO<'a, I> {
+    fn open(&self, m: &mut Machine<I>) -> io::Result<()> {
+        let r = self.io.open(m);
+        self.trace(IoOp::Open, r, m)
+    }
+
+    fn read(&self, m: &mut Machine<I>) -> io::Result<()> {
+        let r = self.io.read(m);
+        self.trace(IoOp::Read, r, m)
+    }
+
+    fn write(&self, m: &mut Machine<I>) -> io::Result<()> {
+        let r = self.io.write(m);
+        self.trace(IoOp::Write, r, m)
+    }
+
+    fn seek(&self, m: &mut Machine<I>) -> io::Result<()> {
+        let r = self.io.seek(m);
+        self.trace(IoOp::Seek, r, m)
+    }
+
+    fn close(&self, m: &mut Machine<I>) -> io::Result<()> {
+        let r = self.io.close(m);
+        self.trace(IoOp::Close, r, m)
+    }
+
+    fn print(&self, m: &mut Machine<I>) -> io::Result<()> {
+        let r = self.io.print(m);
+        self.trace(IoOp::Print, r, m)
+    }
+}
+
+// writes one JSON object per line for every event, stacks are written
+// bottom first and instructions are written using their Display form
+pub struct JsonTracer<W: Write> {
+    w: W,
+    err: Option<io::Error>
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            w,
+            err: None
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.w
+    }
+
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    // tracing can't stop the machine so the first write error is kept here
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.err.take()
+    }
+
+    fn emit(&mut self, v: Value) {
+        if self.err.is_none() {
+            if let Err(e) = writeln!(self.w, "{}", v) {
+                self.err = Some(e);
+            }
+        }
+    }
+
+    fn step<I: Clone + fmt::Display>(&mut self, event: &str, ip: usize, instr: &I, d: &Stack<I>, r: &Stack<usize>) {
+        let stack: Vec<String> = d.iter().map(|i| i.to_string()).collect();
+        let rstack: Vec<usize> = r.iter().copied().collect();
+        self.emit(json!({
+            "event": event,
+            "ip": ip,
+            "instr": instr.to_string(),
+            "stack": stack,
+            "rstack": rstack
+        }));
+    }
+}
+
+impl<I: Clone + fmt::Display, W: Write> Tracer<I> for JsonTracer<W> {
+    fn before(&mut self, ip: usize, instr: &I, d: &Stack<I>, r: &Stack<usize>) {
+        self.step("before", ip, instr, d, r);
+    }
+
+    fn after(&mut self, ip: usize, instr: &I, d: &Stack<I>, r: &Stack<usize>) {
+        self.step("after", ip, instr, d, r);
+    }
+
+    fn io(&mut self, op: IoOp, result: &io::Result<()>, d: &Stack<I>) {
+        let stack: Vec<String> = d.iter().map(|i| i.to_string()).collect();
+        let error = match result {
+            Ok(()) => Value::Null,
+            Err(e) => Value::String(e.to_string())
+        };
+        self.emit(json!({
+            "event": "io",
+            "op": op.to_string(),
+            "error": error,
+            "stack": stack
+        }));
+    }
+}
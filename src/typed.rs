@@ -0,0 +1,127 @@
+use crate::{
+    flow::{
+        self,
+        FlowError,
+        Value
+    },
+    Instruction,
+    Script
+};
+use std::{
+    error,
+    fmt
+};
+
+// what an instruction accepts or leaves in one place on the stack
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type<T> {
+    Is(T),
+    OneOf(Vec<T>),
+    Any,
+    // only for what an instruction leaves: the same as the nth item it took
+    Same(usize)
+}
+
+// the items an instruction takes off the stack and leaves on it, both listed
+// bottom to top
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature<T> {
+    pub takes: Vec<Type<T>>,
+    pub gives: Vec<Type<T>>
+}
+
+impl<T> Signature<T> {
+    pub fn new(takes: Vec<Type<T>>, gives: Vec<Type<T>>) -> Self {
+        Signature { takes, gives }
+    }
+}
+
+// instruction sets that say what types their instructions work on can have
+// scripts checked without running them
+pub trait Typed {
+    type Type: Clone + fmt::Debug + PartialEq;
+
+    fn signature(&self) -> Signature<Self::Type>;
+}
+
+// found holds every type that could be on the stack there, or None if
+// nothing is known about it; slot 0 is the top of the stack
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeError<T> {
+    Flow(FlowError),
+    Mismatch { index: usize, slot: usize, expected: Type<T>, found: Option<Vec<T>> }
+}
+
+impl<T> TypeError<T> {
+    pub fn index(&self) -> usize {
+        match self {
+            TypeError::Flow(e) => e.index(),
+            TypeError::Mismatch { index, .. } => *index
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Display for TypeError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeError::Flow(e) => write!(f, "{}", e),
+            TypeError::Mismatch { index, slot, expected, found: Some(found) } => {
+                write!(f, "instruction at {} expects {:?} at {} from the top but could get {:?}", index, expected, slot, found)
+            },
+            TypeError::Mismatch { index, slot, expected, found: None } => {
+                write!(f, "instruction at {} expects {:?} at {} from the top but could get anything", index, expected, slot)
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> error::Error for TypeError<T> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TypeError::Flow(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+fn accepts<T: PartialEq>(expected: &Type<T>, found: &Value<T>) -> bool {
+    match (expected, found) {
+        (Type::Any, _) | (Type::Same(_), _) => true,
+        (_, None) => false,
+        (Type::Is(t), Some(f)) => f.iter().all(|x| x == t),
+        (Type::OneOf(ts), Some(f)) => f.iter().all(|x| ts.contains(x))
+    }
+}
+
+// simulates the types on the stack along every path through the script,
+// following blocks, jumps and calls, and reports every instruction that could
+// be given the wrong types; problems with the shape of the script stop it
+pub fn check<I>(script: &Script<I>) -> Result<(), Vec<TypeError<I::Type>>>
+where
+    I: Clone + Instruction<I> + Typed
+{
+    let mut errors = Vec::new();
+    let walked = flow::walk(script, I::signature, |ip, takes, taken| {
+        for (n, (expected, found)) in takes.iter().zip(taken).enumerate() {
+            let slot = taken.len() - 1 - n;
+            if accepts(expected, found) {
+                continue;
+            }
+            // coming back with wider types replaces the earlier report
+            let e = TypeError::Mismatch { index: ip, slot, expected: expected.clone(), found: found.clone() };
+            match errors.iter().position(|e| matches!(e, TypeError::Mismatch { index, slot: s, .. } if *index == ip && *s == slot)) {
+                Some(p) => errors[p] = e,
+                None => errors.push(e)
+            }
+        }
+    });
+    if let Err(e) = walked {
+        errors.push(TypeError::Flow(e));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{
+    BlockError,
+    Bytecode,
+    Instruction,
+    Script
+};
+use std::{
+    collections::BTreeMap,
+    error,
+    fmt,
+    rc::Rc
+};
+
+// rules of an instruction set that the verifier can't know about, such as an
+// instruction only being allowed at the end of a script
+pub trait Verify<I: Clone> {
+    fn verify(&self, ip: usize, instr: &I, script: &Script<I>) -> Result<(), String>;
+}
+
+impl<I: Clone, F: Fn(usize, &I, &Script<I>) -> Result<(), String>> Verify<I> for F {
+    fn verify(&self, ip: usize, instr: &I, script: &Script<I>) -> Result<(), String> {
+        self(ip, instr, script)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifyError {
+    TooLong { len: usize, max: usize },
+    Block(BlockError),
+    TargetOutOfRange { index: usize, target: usize },
+    TooManyOpcodes { index: usize, opcode: u8, max: usize },
+    Rule { index: usize, msg: String }
+}
+
+impl VerifyError {
+    // the index of the instruction that broke the rule
+    pub fn index(&self) -> usize {
+        match self {
+            VerifyError::TooLong { max, .. } => *max,
+            VerifyError::Block(e) => e.index(),
+            VerifyError::TargetOutOfRange { index, .. } |
+            VerifyError::TooManyOpcodes { index, .. } |
+            VerifyError::Rule { index, .. } => *index
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::TooLong { len, max } => write!(f, "script has {} instructions, more than {}", len, max),
+            VerifyError::Block(e) => write!(f, "{}", e),
+            VerifyError::TargetOutOfRange { index, target } => write!(f, "instruction at {} goes to {} outside the script", index, target),
+            VerifyError::TooManyOpcodes { index, opcode, max } => write!(f, "opcode {:#04x} at {} is used more than {} times", opcode, index, max),
+            VerifyError::Rule { index, msg } => write!(f, "instruction at {}: {}", index, msg)
+        }
+    }
+}
+
+impl error::Error for VerifyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            VerifyError::Block(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+// checks the structure of a script before it is run, for scripts from
+// sources that aren't trusted
+pub struct Verifier<I: Clone> {
+    max_len: Option<usize>,
+    max_ops: BTreeMap<u8, usize>,
+    rules: Vec<Rc<dyn Verify<I>>>
+}
+
+impl<I: Clone + Bytecode + Instruction<I>> Verifier<I> {
+    pub fn new() -> Self {
+        Verifier {
+            max_len: None,
+            max_ops: BTreeMap::new(),
+            rules: Vec::new()
+        }
+    }
+
+    // limits the number of instructions in the script
+    pub fn max_len(&mut self, n: usize) -> &mut Self {
+        self.max_len = Some(n);
+        self
+    }
+
+    // limits how many times an opcode appears in the script
+    pub fn max_opcode(&mut self, opcode: u8, n: usize) -> &mut Self {
+        self.max_ops.insert(opcode, n);
+        self
+    }
+
+    pub fn rule(&mut self, r: Rc<dyn Verify<I>>) -> &mut Self {
+        self.rules.push(r);
+        self
+    }
+
+    pub fn verify(&self, script: &Script<I>) -> Result<(), VerifyError> {
+        if let Some(max) = self.max_len {
+            if script.len() > max {
+                return Err(VerifyError::TooLong { len: script.len(), max });
+            }
+        }
+
+        if let Err(e) = script.blocks() {
+            return Err(VerifyError::Block(e.clone()));
+        }
+
+        let mut counts: BTreeMap<u8, usize> = BTreeMap::new();
+        for (ip, i) in script.iter().enumerate() {
+            // going to the end of the script finishes it so that's allowed
+            if let Some(target) = i.flow().target() {
+                if target > script.len() {
+                    return Err(VerifyError::TargetOutOfRange { index: ip, target });
+                }
+            }
+
+            let opcode = i.opcode();
+            if let Some(max) = self.max_ops.get(&opcode) {
+                let n = counts.entry(opcode).or_insert(0);
+                *n += 1;
+                if *n > *max {
+                    return Err(VerifyError::TooManyOpcodes { index: ip, opcode, max: *max });
+                }
+            }
+
+            for r in &self.rules {
+                r.verify(ip, i, script).map_err(|msg| VerifyError::Rule { index: ip, msg })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: Clone + Bytecode + Instruction<I>> Default for Verifier<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
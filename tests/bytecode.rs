@@ -0,0 +1,86 @@
+extern crate gsm;
+mod common;
+
+use common::{
+    Instr,
+    NullIO
+};
+use gsm::{
+    BlockError,
+    BytecodeError,
+    Machine,
+    Script
+};
+
+fn script() -> Script<Instr> {
+    let s = r#"":start -10000 double double HALT :double 1 IF DUP + FI RET""#;
+    serde_json::from_str(s).unwrap()
+}
+
+#[test]
+fn bytecode_round_trip() {
+    let script = script();
+    let b = script.to_bytecode();
+    assert_eq!(&b[..5], b"GSMB\x01");
+
+    let again: Script<Instr> = Script::from_bytecode(&b).unwrap();
+    assert_eq!(again, script);
+    assert_eq!(again.label("double"), Some(4));
+
+    let mut machine = Machine::from(again);
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.pop(), Some(Instr::Num(-40000)));
+}
+
+#[test]
+fn bytecode_is_compact() {
+    let script = Script::from(vec![Instr::Num(123456), Instr::Num(-1), Instr::Add]);
+    let b = script.to_bytecode();
+
+    // header, count, Num, zigzag 123456 in three bytes, Num, -1, Add, no labels
+    assert_eq!(b, vec![b'G', b'S', b'M', b'B', 1, 3, 0x01, 0x80, 0x89, 0x0f, 0x01, 0x01, 0x02, 0]);
+    assert!(b.len() < script.to_string().len() + 5);
+}
+
+#[test]
+fn bytecode_bad_header() {
+    let mut b = script().to_bytecode();
+    assert_eq!(Script::<Instr>::from_bytecode(&b[..3]), Err(BytecodeError::BadMagic));
+
+    b[4] = 2;
+    assert_eq!(Script::<Instr>::from_bytecode(&b), Err(BytecodeError::UnsupportedVersion(2)));
+    assert_eq!(Script::<Instr>::from_bytecode(b"GSMB"), Err(BytecodeError::Truncated(4)));
+}
+
+#[test]
+fn bytecode_bad_body() {
+    let b = script().to_bytecode();
+
+    // every shorter prefix is rejected
+    for n in 5..b.len() {
+        assert!(Script::<Instr>::from_bytecode(&b[..n]).is_err());
+    }
+
+    let mut trailing = b.clone();
+    trailing.push(0);
+    assert_eq!(Script::<Instr>::from_bytecode(&trailing), Err(BytecodeError::TrailingBytes(b.len())));
+
+    let unknown = vec![b'G', b'S', b'M', b'B', 1, 2, 0x02, 0xff, 0];
+    assert_eq!(Script::<Instr>::from_bytecode(&unknown), Err(BytecodeError::UnknownOpcode { offset: 7, opcode: 0xff }));
+
+    let overflow = vec![b'G', b'S', b'M', b'B', 1, 1, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0];
+    assert_eq!(Script::<Instr>::from_bytecode(&overflow), Err(BytecodeError::VarintOverflow(7)));
+
+    // a count larger than the rest of the input isn't trusted
+    let huge = vec![b'G', b'S', b'M', b'B', 1, 0xff, 0xff, 0xff, 0xff, 0x0f];
+    assert_eq!(Script::<Instr>::from_bytecode(&huge), Err(BytecodeError::Truncated(5)));
+
+    let utf8 = vec![b'G', b'S', b'M', b'B', 1, 1, 0x09, 1, 0xc0, 0];
+    assert_eq!(Script::<Instr>::from_bytecode(&utf8), Err(BytecodeError::InvalidUtf8(7)));
+
+    let label = vec![b'G', b'S', b'M', b'B', 1, 1, 0x02, 1, 1, b'x', 2];
+    assert_eq!(Script::<Instr>::from_bytecode(&label), Err(BytecodeError::BadLabel("x".to_string())));
+
+    let unclosed = Script::from(vec![Instr::If, Instr::Num(1)]).to_bytecode();
+    assert_eq!(Script::<Instr>::from_bytecode(&unclosed), Err(BytecodeError::Block(BlockError::Unclosed(0))));
+}
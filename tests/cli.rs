@@ -0,0 +1,86 @@
+#![cfg(feature = "cli")]
+
+use std::{
+    io::Write,
+    process::{
+        Command,
+        Output,
+        Stdio
+    }
+};
+
+fn gsm(args: &[&str], input: &str) -> Output {
+    let mut c = Command::new(env!("CARGO_BIN_EXE_gsm"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    c.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    c.wait_with_output().unwrap()
+}
+
+fn stdout(o: &Output) -> String {
+    String::from_utf8_lossy(&o.stdout).to_string()
+}
+
+fn stderr(o: &Output) -> String {
+    String::from_utf8_lossy(&o.stderr).to_string()
+}
+
+#[test]
+fn cli_run() {
+    let o = gsm(&["run"], "1 2 + \"done\"");
+    assert!(o.status.success());
+    assert_eq!(stdout(&o), "done\n3\n");
+
+    let o = gsm(&["run"], "\"hello world\" PRINT 7");
+    assert_eq!(stdout(&o), "hello world\n7\n");
+
+    let o = gsm(&["run", "-"], "1 0 /");
+    assert_eq!(o.status.code(), Some(1));
+    assert!(stderr(&o).starts_with("<stdin>: '/' at 2:"));
+
+    let o = gsm(&["run", "--fuel", "2"], "1 2 +");
+    assert_eq!(o.status.code(), Some(1));
+    assert!(stderr(&o).contains("out of gas"));
+}
+
+#[test]
+fn cli_inspect() {
+    let o = gsm(&["parse"], ":top 1 2");
+    assert_eq!(stdout(&o), "<stdin>: 2 instructions, 1 labels\n");
+
+    let o = gsm(&["parse"], "1 \"two\nFI");
+    assert_eq!(o.status.code(), Some(1));
+    assert_eq!(stderr(&o), "<stdin>: line 1, column 3: unterminated string\n");
+
+    let o = gsm(&["fmt"], "true IF 1 FI");
+    assert_eq!(stdout(&o), "true\nIF\n    1\nFI\n");
+
+    let o = gsm(&["disasm"], "1 HALT");
+    assert_eq!(stdout(&o), "0  1\n1  HALT                     # halt\n");
+
+    let o = gsm(&["verify"], "IF");
+    assert_eq!(o.status.code(), Some(1));
+    let o = gsm(&["verify", "--max-len", "2"], "1 2");
+    assert_eq!(stdout(&o), "<stdin>: ok\n");
+}
+
+#[test]
+fn cli_usage() {
+    assert_eq!(gsm(&[], "").status.code(), Some(2));
+    assert_eq!(gsm(&["bogus"], "").status.code(), Some(2));
+    assert_eq!(gsm(&["run", "--max-len", "1"], "").status.code(), Some(2));
+    assert_eq!(gsm(&["run", "a", "b"], "").status.code(), Some(2));
+    assert_eq!(gsm(&["run", "/no/such/file"], "").status.code(), Some(1));
+    assert!(gsm(&["help"], "").status.success());
+}
+
+#[test]
+fn cli_repl() {
+    let o = gsm(&["repl"], "1 2\n+\n.undo\n");
+    assert!(o.status.success());
+    assert_eq!(stdout(&o), "> 2\n1\n> 3\n> 2\n1\n> \n");
+}
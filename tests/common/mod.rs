@@ -0,0 +1,346 @@
+// the instruction set and io the tests that don't need one of their own share;
+// each test crate only uses some of it
+#![allow(dead_code)]
+
+use gsm::{
+    AppIO,
+    Block,
+    Bytecode,
+    BytecodeError,
+    Cause,
+    Decoder,
+    Effect,
+    Encoder,
+    Flow,
+    Instruction,
+    Machine,
+    MachineError,
+    Optimize,
+    Signature,
+    StackEffect,
+    Type,
+    Typed
+};
+use serde::{
+    de,
+    Deserialize,
+    Deserializer
+};
+use std::{
+    fmt,
+    io
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr {
+    Num(isize),
+    Add,
+    Dup,
+    Jmp(usize),
+    Jz(usize),
+    // jumps to the target on top of the stack if the value under it isn't zero
+    Jnz,
+    Call(usize),
+    Ret,
+    Halt,
+    // a block that is skipped if the number on top of the stack is zero
+    If,
+    Fi,
+    Expensive,
+    Read,
+    DupRead,
+    // anything else is a word, which calls the code at the label of the same
+    // name
+    Word(String)
+}
+
+struct InstrVisitor;
+
+impl<'de> de::Visitor<'de> for InstrVisitor {
+    type Value = Instr;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Instr token")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        let target = |p: &str| v.strip_prefix(p).and_then(|t| t.parse::<usize>().ok());
+        match v {
+            "+" => Ok(Instr::Add),
+            "DUP" => Ok(Instr::Dup),
+            "JNZ" => Ok(Instr::Jnz),
+            "RET" => Ok(Instr::Ret),
+            "HALT" => Ok(Instr::Halt),
+            "IF" => Ok(Instr::If),
+            "FI" => Ok(Instr::Fi),
+            "EXPENSIVE" => Ok(Instr::Expensive),
+            "READ" => Ok(Instr::Read),
+            "DUPREAD" => Ok(Instr::DupRead),
+            &_ => {
+                if let Ok(i) = v.parse::<isize>() {
+                    Ok(Instr::Num(i))
+                } else if let Some(t) = target("JMP:") {
+                    Ok(Instr::Jmp(t))
+                } else if let Some(t) = target("JZ:") {
+                    Ok(Instr::Jz(t))
+                } else if let Some(t) = target("CALL:") {
+                    Ok(Instr::Call(t))
+                } else {
+                    Ok(Instr::Word(v.to_string()))
+                }
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Instr {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Instr, D::Error> {
+        d.deserialize_any(InstrVisitor)
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Num(n) => write!(f, "{}", n),
+            Instr::Add => write!(f, "+"),
+            Instr::Dup => write!(f, "DUP"),
+            Instr::Jmp(t) => write!(f, "JMP:{}", t),
+            Instr::Jz(t) => write!(f, "JZ:{}", t),
+            Instr::Jnz => write!(f, "JNZ"),
+            Instr::Call(t) => write!(f, "CALL:{}", t),
+            Instr::Ret => write!(f, "RET"),
+            Instr::Halt => write!(f, "HALT"),
+            Instr::If => write!(f, "IF"),
+            Instr::Fi => write!(f, "FI"),
+            Instr::Expensive => write!(f, "EXPENSIVE"),
+            Instr::Read => write!(f, "READ"),
+            Instr::DupRead => write!(f, "DUPREAD"),
+            Instr::Word(w) => write!(f, "{}", w)
+        }
+    }
+}
+
+pub struct NullIO;
+
+impl<I: Clone> AppIO<I> for NullIO {
+    fn open(&self, _m: &mut Machine<I>) -> io::Result<()> { Ok(()) }
+    fn read(&self, _m: &mut Machine<I>) -> io::Result<()> { Ok(()) }
+    fn write(&self, _m: &mut Machine<I>) -> io::Result<()> { Ok(()) }
+    fn seek(&self, _m: &mut Machine<I>) -> io::Result<()> { Ok(()) }
+    fn close(&self, _m: &mut Machine<I>) -> io::Result<()> { Ok(()) }
+}
+
+impl Instruction<Instr> for Instr {
+    fn execute(&self, ip: usize, m: &mut Machine<Instr>, io: &dyn AppIO<Instr>) -> Result<(), MachineError<Instr>> {
+        match self {
+            Instr::Num(_) => {
+                m.push(self.clone());
+                m.next(ip);
+            },
+            Instr::Add => {
+                match (m.pop(), m.pop()) {
+                    (Some(Instr::Num(r)), Some(Instr::Num(l))) => m.push(Instr::Num(l + r)),
+                    (Some(_), Some(_)) => return Err(Cause::TypeMismatch.into()),
+                    _ => return Err(Cause::StackUnderflow.into())
+                }
+                m.next(ip);
+            },
+            Instr::Dup => {
+                match m.pop() {
+                    Some(i) => {
+                        m.push(i.clone());
+                        m.push(i);
+                    },
+                    None => return Err(Cause::StackUnderflow.into())
+                }
+                m.next(ip);
+            },
+            Instr::Jmp(t) => m.jump(*t),
+            Instr::Jz(t) => {
+                match m.pop() {
+                    Some(Instr::Num(0)) => m.jump(*t),
+                    Some(Instr::Num(_)) => m.next(ip),
+                    _ => return Err(Cause::TypeMismatch.into())
+                }
+            },
+            Instr::Jnz => {
+                match (m.pop(), m.pop()) {
+                    (Some(Instr::Num(_)), Some(Instr::Num(0))) => m.next(ip),
+                    (Some(Instr::Num(t)), Some(Instr::Num(_))) => m.jump(t as usize),
+                    _ => return Err(Cause::TypeMismatch.into())
+                }
+            },
+            Instr::Call(t) => m.call(*t, ip + 1),
+            Instr::Ret => m.ret()?,
+            Instr::Halt => m.halt(),
+            Instr::If => {
+                match m.pop() {
+                    Some(Instr::Num(0)) => {
+                        let info = m.block(ip).ok_or(Cause::InvalidInstruction)?;
+                        m.next(info.close);
+                    },
+                    Some(Instr::Num(_)) => m.next(ip),
+                    _ => return Err(Cause::TypeMismatch.into())
+                }
+            },
+            Instr::Fi => m.next(ip),
+            Instr::Expensive => m.next(ip),
+            Instr::Read => {
+                io.read(m)?;
+                m.next(ip);
+            },
+            Instr::DupRead => {
+                let top = m.pop().ok_or(Cause::StackUnderflow)?;
+                m.try_push(top.clone())?;
+                m.try_push(top)?;
+                io.read(m)?;
+                m.next(ip);
+            },
+            Instr::Word(w) => {
+                match m.label(w) {
+                    Some(target) => m.call(target, ip + 1),
+                    None => return Err(Cause::Other(format!("unknown word '{}'", w)).into())
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn cost(&self) -> u64 {
+        match self {
+            Instr::Expensive => 10,
+            _ => 1
+        }
+    }
+
+    fn block(&self) -> Option<Block> {
+        match self {
+            Instr::If => Some(Block::Open),
+            Instr::Fi => Some(Block::Close),
+            _ => None
+        }
+    }
+
+    fn flow(&self) -> Flow {
+        match self {
+            Instr::Jmp(t) => Flow::Jump(*t),
+            Instr::Jz(t) => Flow::Branch(*t),
+            Instr::Call(t) => Flow::Call(*t),
+            Instr::Ret => Flow::Return,
+            Instr::Halt => Flow::Halt,
+            Instr::Jnz | Instr::Word(_) => Flow::Dynamic,
+            _ => Flow::Next
+        }
+    }
+}
+
+impl Bytecode for Instr {
+    fn opcode(&self) -> u8 {
+        match self {
+            Instr::Num(_) => 0x01,
+            Instr::Add => 0x02,
+            Instr::Jmp(_) => 0x03,
+            Instr::Jz(_) => 0x04,
+            Instr::Call(_) => 0x05,
+            Instr::Ret => 0x06,
+            Instr::Halt => 0x07,
+            Instr::If => 0x08,
+            Instr::Word(_) => 0x09,
+            Instr::Fi => 0x0a,
+            Instr::Dup => 0x0b,
+            Instr::Jnz => 0x0c,
+            Instr::Expensive => 0x0d,
+            Instr::Read => 0x0e,
+            Instr::DupRead => 0x0f
+        }
+    }
+
+    fn encode(&self, e: &mut Encoder) {
+        match self {
+            Instr::Num(n) => e.signed(*n as i64),
+            Instr::Jmp(t) | Instr::Jz(t) | Instr::Call(t) => e.varint(*t as u64),
+            Instr::Word(w) => e.str(w),
+            _ => {}
+        }
+    }
+
+    fn decode(opcode: u8, d: &mut Decoder) -> Result<Self, BytecodeError> {
+        match opcode {
+            0x01 => Ok(Instr::Num(d.signed()? as isize)),
+            0x02 => Ok(Instr::Add),
+            0x03 => Ok(Instr::Jmp(d.varint()? as usize)),
+            0x04 => Ok(Instr::Jz(d.varint()? as usize)),
+            0x05 => Ok(Instr::Call(d.varint()? as usize)),
+            0x06 => Ok(Instr::Ret),
+            0x07 => Ok(Instr::Halt),
+            0x08 => Ok(Instr::If),
+            0x09 => Ok(Instr::Word(d.str()?.to_string())),
+            0x0a => Ok(Instr::Fi),
+            0x0b => Ok(Instr::Dup),
+            0x0c => Ok(Instr::Jnz),
+            0x0d => Ok(Instr::Expensive),
+            0x0e => Ok(Instr::Read),
+            0x0f => Ok(Instr::DupRead),
+            _ => Err(d.unknown(opcode))
+        }
+    }
+}
+
+impl Effect for Instr {
+    fn effect(&self) -> StackEffect {
+        match self {
+            Instr::Num(_) => StackEffect::new(0, 1),
+            Instr::Add => StackEffect::new(2, 1),
+            Instr::Dup => StackEffect::new(1, 2),
+            Instr::Jz(_) | Instr::If => StackEffect::new(1, 0),
+            Instr::Jnz => StackEffect::new(2, 0),
+            Instr::DupRead => StackEffect::new(1, 2),
+            _ => StackEffect::new(0, 0)
+        }
+    }
+}
+
+// only numbers go on the stack but If wants a number that came from Add
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    Literal,
+    Sum
+}
+
+impl Typed for Instr {
+    type Type = Kind;
+
+    fn signature(&self) -> Signature<Kind> {
+        let num = || Type::OneOf(vec![Kind::Literal, Kind::Sum]);
+        match self {
+            Instr::Num(_) => Signature::new(vec![], vec![Type::Is(Kind::Literal)]),
+            Instr::Add => Signature::new(vec![num(), num()], vec![Type::Is(Kind::Sum)]),
+            Instr::Jz(_) => Signature::new(vec![num()], vec![]),
+            Instr::If => Signature::new(vec![Type::Is(Kind::Sum)], vec![]),
+            _ => Signature::new(vec![], vec![])
+        }
+    }
+}
+
+impl Optimize for Instr {
+    fn is_constant(&self) -> bool {
+        matches!(self, Instr::Num(_))
+    }
+
+    fn fold(&self, args: &[Instr]) -> Option<Vec<Instr>> {
+        match (self, args) {
+            (Instr::Add, [Instr::Num(l), Instr::Num(r)]) => Some(vec![Instr::Num(l + r)]),
+            _ => None
+        }
+    }
+
+    fn retarget(&self, map: &dyn Fn(usize) -> usize) -> Instr {
+        match self {
+            Instr::Jmp(t) => Instr::Jmp(map(*t)),
+            Instr::Jz(t) => Instr::Jz(map(*t)),
+            Instr::Call(t) => Instr::Call(map(*t)),
+            i => i.clone()
+        }
+    }
+}
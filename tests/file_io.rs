@@ -1,3 +1,4 @@
+#![allow(clippy::unnecessary_cast)]
 extern crate gsm;
 use bytes::{
     BufMut,
@@ -5,18 +6,32 @@ use bytes::{
     BytesMut
 };
 use gsm::{
+    literal,
     AppIO,
+    Cause,
+    FlowError,
     Instruction,
+    IoOp,
+    Literal,
+    Literals,
     Machine,
-    Script
+    MachineBuilder,
+    MachineError,
+    Script,
+    Signature,
+    Stack,
+    Tracer,
+    Type,
+    Typed,
+    TypeError
 };
-use hex;
 use serde::{
     de,
     Deserialize,
     Deserializer
 };
 use std::{
+    env,
     fmt,
     fs::{
         self,
@@ -31,6 +46,7 @@ use std::{
         SeekFrom,
         Write
     },
+    cell::RefCell,
     path::PathBuf,
     rc::Rc,
     str::FromStr
@@ -66,30 +82,30 @@ impl<'de> de::Visitor<'de> for InstrVisitor {
     }
 
     fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
-        let mv = gsm::ModeVisitor;
-        let wv = gsm::WhenceVisitor;
-
-        if let Ok(m) = mv.visit_str::<E>(v) {
-            return Ok(Instr::Mode(m));
-        } else if let Ok(w) = wv.visit_str::<E>(v) {
-            return Ok(Instr::Whence(w));
-        } else {
-            match v {
-                "OPEN" => return Ok(Instr::Open),
-                "READ" => return Ok(Instr::Read),
-                "WRITE" => return Ok(Instr::Write),
-                "SEEK" => return Ok(Instr::Seek),
-                "CLOSE" => return Ok(Instr::Close),
-                &_ => {
-                    if let Ok(i) = v.parse::<isize>() {
-                        return Ok(Instr::Num(i));
-                    } else if let Ok(h) = hex::decode(v) {
-                        return Ok(Instr::Binary(Bytes::copy_from_slice(h.as_slice())));
-                    } else {
-                        return Ok(Instr::Text(v.to_string()));
-                    }
-                }
-            }
+        match v {
+            "OPEN" => return Ok(Instr::Open),
+            "READ" => return Ok(Instr::Read),
+            "WRITE" => return Ok(Instr::Write),
+            "SEEK" => return Ok(Instr::Seek),
+            "CLOSE" => return Ok(Instr::Close),
+            &_ => {}
+        }
+
+        // file flags first, then 0x binary data, numbers and anything else
+        // is text
+        let mut l = Literals::new();
+        l.kind(literal::Kind::Mode)
+         .kind(literal::Kind::Whence)
+         .kind(literal::Kind::Bytes)
+         .kind(literal::Kind::Int)
+         .kind(literal::Kind::Text);
+        match l.parse(v) {
+            Some(Literal::Mode(m)) => Ok(Instr::Mode(m)),
+            Some(Literal::Whence(w)) => Ok(Instr::Whence(w)),
+            Some(Literal::Bytes(b)) => Ok(Instr::Binary(Bytes::from(b))),
+            Some(Literal::Int(i)) => Ok(Instr::Num(i as isize)),
+            Some(Literal::Text(s)) => Ok(Instr::Text(s)),
+            _ => Err(E::custom(format!("failed to parse '{}'", v)))
         }
     }
 }
@@ -109,8 +125,11 @@ impl fmt::Display for Instr {
             Instr::Seek => write!(f, "SEEK"),
             Instr::Close => write!(f, "CLOSE"),
             Instr::Num(val) => write!(f, "{}", val),
-            Instr::Binary(b) => write!(f, "{}", hex::encode(b.as_ref())),
-            Instr::Text(s) => write!(f, "{}", s),
+            Instr::Binary(b) => write!(f, "0x{}", hex::encode(b.as_ref())),
+            Instr::Text(s) => match de::Visitor::visit_str::<de::value::Error>(InstrVisitor, s) {
+                Ok(Instr::Text(_)) if gsm::literal::bare(s) => write!(f, "{}", s),
+                _ => write!(f, "{}", gsm::literal::quote(s))
+            },
             Instr::Whence(w) => write!(f, "{}", w),
             Instr::Mode(m) => write!(f, "{}", m),
             Instr::IOHandle{ f:_, binary:_ } => panic!("cannot serialie IOHandle")
@@ -118,9 +137,41 @@ impl fmt::Display for Instr {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    Num,
+    Binary,
+    Text,
+    Whence,
+    Mode,
+    Handle
+}
+
+impl Typed for Instr {
+    type Type = Kind;
+
+    fn signature(&self) -> Signature<Kind> {
+        let data = || Type::OneOf(vec![Kind::Text, Kind::Binary]);
+        match self {
+            Instr::Open => Signature::new(vec![Type::Is(Kind::Text), Type::Is(Kind::Mode)], vec![Type::Is(Kind::Handle)]),
+            Instr::Read => Signature::new(vec![Type::Is(Kind::Handle), Type::Is(Kind::Num)], vec![data(), Type::Is(Kind::Handle)]),
+            Instr::Write => Signature::new(vec![Type::Is(Kind::Handle), data()], vec![Type::Is(Kind::Handle)]),
+            Instr::Seek => Signature::new(vec![Type::Is(Kind::Handle), Type::Is(Kind::Num), Type::Is(Kind::Whence)], vec![Type::Is(Kind::Handle)]),
+            Instr::Close => Signature::new(vec![Type::Is(Kind::Handle)], vec![]),
+            Instr::Num(_) => Signature::new(vec![], vec![Type::Is(Kind::Num)]),
+            Instr::Binary(_) => Signature::new(vec![], vec![Type::Is(Kind::Binary)]),
+            Instr::Text(_) => Signature::new(vec![], vec![Type::Is(Kind::Text)]),
+            Instr::Whence(_) => Signature::new(vec![], vec![Type::Is(Kind::Whence)]),
+            Instr::Mode(_) => Signature::new(vec![], vec![Type::Is(Kind::Mode)]),
+            Instr::IOHandle{ .. } => Signature::new(vec![], vec![Type::Is(Kind::Handle)])
+        }
+    }
+}
+
 struct FileIO;
 
 impl AppIO<Instr> for FileIO {
+    #[allow(clippy::needless_return)]
     fn open(&self, m: &mut Machine<Instr>) -> io::Result<()> {
         if let Some(Instr::Mode(mode)) = m.pop() {
             if let Some(Instr::Text(p)) = m.pop() {
@@ -169,6 +220,7 @@ impl AppIO<Instr> for FileIO {
         Ok(())
     }
 
+    #[allow(clippy::needless_return, clippy::unused_io_amount, clippy::io_other_error)]
     fn write(&self, m: &mut Machine<Instr>) -> io::Result<()> {
         match m.pop() {
             Some(Instr::Binary(b)) => {
@@ -222,8 +274,24 @@ impl AppIO<Instr> for FileIO {
     }
 }
 
+#[derive(Default)]
+struct IoRecorder {
+    instrs: usize,
+    ops: Vec<(IoOp, bool)>
+}
+
+impl Tracer<Instr> for IoRecorder {
+    fn before(&mut self, _ip: usize, _instr: &Instr, _d: &Stack<Instr>, _r: &Stack<usize>) {
+        self.instrs += 1;
+    }
+
+    fn io(&mut self, op: IoOp, result: &io::Result<()>, _d: &Stack<Instr>) {
+        self.ops.push((op, result.is_ok()));
+    }
+}
+
 impl Instruction<Instr> for Instr {
-    fn execute(&self, ip: usize, m: &mut Machine<Instr>, io: &dyn AppIO<Instr>) {
+    fn execute(&self, ip: usize, m: &mut Machine<Instr>, io: &dyn AppIO<Instr>) -> Result<(), MachineError<Instr>> {
         match self {
             Instr::Num(_) |
             Instr::Binary(_) |
@@ -231,41 +299,21 @@ impl Instruction<Instr> for Instr {
             Instr::Whence(_) |
             Instr::Mode(_) => {
                 m.push(self.clone());
-                m.pushr(ip + 1);
-                return;
-            },
-            Instr::Open => {
-                if let Err(e) = io.open(m) {
-                    panic!(format!("{}", e));
-                }
-                m.pushr(ip + 1);
-            },
-            Instr::Read => {
-                if let Err(e) = io.read(m) {
-                    panic!(format!("{}", e));
-                }
-                m.pushr(ip + 1);
             },
-            Instr::Write => {
-                if let Err(e) = io.write(m) {
-                    panic!(format!("{}", e));
-                }
-                m.pushr(ip + 1);
-            },
-            Instr::Seek => {
-                if let Err(e) = io.seek(m) {
-                    panic!(format!("{}", e));
-                }
-                m.pushr(ip + 1);
-            },
-            Instr::Close => {
-                if let Err(e) = io.close(m) {
-                    panic!(format!("{}", e));
-                }
-                m.pushr(ip + 1);
-            },
-            Instr::IOHandle{ f:_, binary:_ } => panic!()
+            Instr::Open => io.open(m)?,
+            Instr::Read => io.read(m)?,
+            Instr::Write => io.write(m)?,
+            Instr::Seek => io.seek(m)?,
+            Instr::Close => io.close(m)?,
+            Instr::IOHandle{ f:_, binary:_ } => return Err(Cause::InvalidInstruction.into())
         }
+        m.pushr(ip + 1);
+        Ok(())
+    }
+
+    // quoted tokens are always text
+    fn quoted(text: &str) -> Option<Instr> {
+        Some(Instr::Text(text.to_string()))
     }
 }
 
@@ -432,4 +480,162 @@ fn deserialization_json() {
     fs::remove_file("script.txt").unwrap();
 }
 
+#[test]
+fn open_missing_file() {
+    let script = Script::from(vec![
+        Instr::Text("does-not-exist.txt".to_string()),
+        Instr::Mode(gsm::Mode::from_str("r").unwrap()),
+        Instr::Open
+    ]);
+    let mut machine = Machine::from(script);
+    let err = machine.execute(&FileIO).unwrap_err();
+
+    // the error should point at the 'OPEN' and carry the io error
+    assert_eq!(err.ip, 2);
+    match (err.instr, err.cause) {
+        (Some(Instr::Open), Cause::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+        _ => panic!()
+    }
+}
+
+#[test]
+fn trace_io() {
+    let script = Script::from(vec![
+        Instr::Text("LICENSE".to_string()),
+        Instr::Mode(gsm::Mode::from_str("r").unwrap()),
+        Instr::Open,
+        Instr::Num(128),
+        Instr::Read,
+        Instr::Close,
+        Instr::Text("does-not-exist.txt".to_string()),
+        Instr::Mode(gsm::Mode::from_str("r").unwrap()),
+        Instr::Open
+    ]);
+    let recorder = Rc::new(RefCell::new(IoRecorder::default()));
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .tracer(recorder.clone())
+        .build();
+    assert!(machine.execute(&FileIO).is_err());
+
+    // every io call is traced, including the failing open at the end
+    let r = recorder.borrow();
+    assert_eq!(r.instrs, 9);
+    assert_eq!(r.ops, vec![
+        (IoOp::Open, true),
+        (IoOp::Read, true),
+        (IoOp::Close, true),
+        (IoOp::Open, false)
+    ]);
+}
+
+#[test]
+fn deserialization_quoted() {
+    let path = env::temp_dir().join("gsm quoted script.txt");
+    let s = format!("{} w OPEN \"hello, world\\n\" WRITE CLOSE",
+                    gsm::literal::quote(path.to_str().unwrap()));
+    let script: Script<Instr> = serde_json::from_str(&serde_json::to_string(&s).unwrap()).unwrap();
+    let mut machine = Machine::from(script);
+    let result = machine.execute(&FileIO).unwrap();
+
+    // the stack should be empty
+    assert_eq!(result.size(), 0);
+
+    let data = fs::read_to_string(&path).unwrap();
+    assert_eq!(data, "hello, world\n");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn serialization_quoted() {
+    let script = Script::from(vec![
+        Instr::Text("a path/with spaces.txt".to_string()),
+        Instr::Mode(gsm::Mode::from_str("w").unwrap()),
+        Instr::Open,
+        Instr::Text("say \"hi\"\\\ttab".to_string()),
+        Instr::Text(":not a label".to_string()),
+        Instr::Write,
+        Instr::Text("OPEN".to_string()),
+        Instr::Text("r".to_string()),
+        Instr::Text("12".to_string())
+    ]);
+    let s = serde_json::to_string(&script).unwrap();
+    assert_eq!(s, r#""\"a path/with spaces.txt\" w OPEN \"say \\\"hi\\\"\\\\\\ttab\" \":not a label\" WRITE \"OPEN\" \"r\" \"12\"""#);
+
+    // every value comes back the same
+    let script: Script<Instr> = serde_json::from_str(&s).unwrap();
+    match script.get(3) {
+        Some(Instr::Text(t)) => assert_eq!(t, "say \"hi\"\\\ttab"),
+        _ => panic!()
+    }
+    match script.get(4) {
+        Some(Instr::Text(t)) => assert_eq!(t, ":not a label"),
+        _ => panic!()
+    }
+    for (n, t) in [(6, "OPEN"), (7, "r"), (8, "12")].iter() {
+        match script.get(*n) {
+            Some(Instr::Text(s)) => assert_eq!(s, *t),
+            _ => panic!()
+        }
+    }
+    assert_eq!(serde_json::to_string(&script).unwrap(), s);
+}
+
+#[test]
+fn deserialization_bad_quotes() {
+    for s in &[
+        r#""\"unterminated OPEN""#,
+        r#""\"no space\"OPEN""#,
+        r#""mid\"word\"""#,
+        r#""\"bad \\q escape\"""#,
+        r#""\"bad \\u{110000} escape\"""#
+    ] {
+        assert!(serde_json::from_str::<Script<Instr>>(s).is_err());
+    }
+
+    let s = r#""\"\\u{48}\\u{49}\"""#;
+    let script: Script<Instr> = serde_json::from_str(s).unwrap();
+    match script.get(0) {
+        Some(Instr::Text(t)) => assert_eq!(t, "HI"),
+        _ => panic!()
+    }
+}
 
+#[test]
+fn deserialization_json_sequence() {
+    let s = r#"["sequence script.txt", "w", "OPEN", "blah blah", "WRITE", "CLOSE"]"#;
+    let script: Script<Instr> = gsm::script::any::deserialize(&mut serde_json::Deserializer::from_str(s)).unwrap();
+    assert_eq!(script.len(), 6);
+    let mut machine = Machine::from(script);
+    let result = machine.execute(&FileIO).unwrap();
+
+    // the stack should be empty
+    assert_eq!(result.size(), 0);
+
+    let meta = fs::metadata("sequence script.txt").unwrap();
+    assert_eq!(meta.len(), 9);
+    fs::remove_file("sequence script.txt").unwrap();
+}
+
+#[test]
+fn type_check() {
+    let script = Script::<Instr>::parse("script.txt w OPEN blah WRITE CLOSE").unwrap();
+    gsm::typed::check(&script).unwrap();
+
+    let script = Script::<Instr>::parse("script.txt r OPEN 0 START SEEK 10 READ").unwrap();
+    gsm::typed::check(&script).unwrap();
+
+    // the mode and path are the wrong way around, and the number isn't data
+    let script = Script::<Instr>::parse("w script.txt OPEN 12 WRITE CLOSE").unwrap();
+    let errs = gsm::typed::check(&script).unwrap_err();
+    assert_eq!(errs, vec![
+        TypeError::Mismatch { index: 2, slot: 1, expected: Type::Is(Kind::Text), found: Some(vec![Kind::Mode]) },
+        TypeError::Mismatch { index: 2, slot: 0, expected: Type::Is(Kind::Mode), found: Some(vec![Kind::Text]) },
+        TypeError::Mismatch { index: 4, slot: 0, expected: Type::OneOf(vec![Kind::Text, Kind::Binary]), found: Some(vec![Kind::Num]) }
+    ]);
+    assert_eq!(errs[0].to_string(), "instruction at 2 expects Is(Text) at 1 from the top but could get [Mode]");
+
+    let script = Script::<Instr>::parse("script.txt OPEN").unwrap();
+    let errs = gsm::typed::check(&script).unwrap_err();
+    assert_eq!(errs, vec![TypeError::Flow(FlowError::Underflow { index: 1, depth: 1, takes: 2 })]);
+}
@@ -0,0 +1,107 @@
+extern crate gsm;
+mod common;
+
+use common::{
+    Instr,
+    NullIO
+};
+use gsm::{
+    LabelError,
+    Machine,
+    Script
+};
+
+#[test]
+fn label_table() {
+    let s = r#"":start 10 double double HALT :double DUP + RET :end""#;
+    let script: Script<Instr> = serde_json::from_str(s).unwrap();
+    assert_eq!(script.len(), 7);
+    assert_eq!(script.label("start"), Some(0));
+    assert_eq!(script.label("double"), Some(4));
+    assert_eq!(script.label("end"), Some(7));
+    assert_eq!(script.label("nope"), None);
+    assert_eq!(script.labels().len(), 3);
+}
+
+#[test]
+fn call_by_name() {
+    let s = r#""10 double double HALT :double DUP + RET""#;
+    let script: Script<Instr> = serde_json::from_str(s).unwrap();
+    let mut machine = Machine::from(script);
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.size(), 1);
+    assert_eq!(result.pop(), Some(Instr::Num(40)));
+}
+
+#[test]
+fn jump_to_label() {
+    // count down from 3, leaving each value on the stack
+    let s = r#""3 :loop DUP -1 + DUP @loop JNZ""#;
+    let script: Script<Instr> = serde_json::from_str(s).unwrap();
+
+    // the reference is resolved to the index of the labelled instruction
+    assert_eq!(script.get(5), Some(Instr::Num(1)));
+
+    let mut machine = Machine::from(script);
+    let result = machine.execute(&NullIO).unwrap();
+    let values: Vec<Instr> = result.iter().cloned().collect();
+    assert_eq!(values, vec![
+        Instr::Num(3),
+        Instr::Num(2),
+        Instr::Num(1),
+        Instr::Num(0)
+    ]);
+}
+
+#[test]
+fn forward_reference() {
+    let s = r#""0 @skip JNZ 1 @skip JNZ 99 :skip 7""#;
+    let script: Script<Instr> = serde_json::from_str(s).unwrap();
+    let mut machine = Machine::from(script);
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.pop(), Some(Instr::Num(7)));
+    assert_eq!(result.size(), 0);
+}
+
+#[test]
+fn serialize_labels() {
+    let s = r#"":start 10 double HALT :double :twice DUP + RET :end""#;
+    let script: Script<Instr> = serde_json::from_str(s).unwrap();
+    assert_eq!(serde_json::to_string(&script).unwrap(), s);
+
+    let mut script = Script::from(vec![Instr::Num(1), Instr::Halt]);
+    script.add_label("stop", 1).unwrap();
+    assert_eq!(script.to_string(), "1 :stop HALT");
+
+    // names that wouldn't read back as the same label
+    for name in &["", "two words", "tab\there", "say\"hi\""] {
+        assert_eq!(script.add_label(name, 0), Err(LabelError(name.to_string())));
+    }
+    assert_eq!(script.add_label("", 0).unwrap_err().to_string(), "'' can't be a label");
+    assert_eq!(script.to_string(), "1 :stop HALT");
+}
+
+#[test]
+fn bad_labels() {
+    let unknown = r#""@nowhere JNZ""#;
+    let err = serde_json::from_str::<Script<Instr>>(unknown).unwrap_err();
+    assert!(err.to_string().contains("unknown label 'nowhere'"));
+
+    let twice = r#"":a 1 :a 2""#;
+    let err = serde_json::from_str::<Script<Instr>>(twice).unwrap_err();
+    assert!(err.to_string().contains("label 'a' defined twice"));
+
+    let empty = r#""1 : 2""#;
+    assert!(serde_json::from_str::<Script<Instr>>(empty).is_err());
+}
+
+#[test]
+fn pretty_labels() {
+    let s = r#"":start 10 double HALT :double DUP + RET :end""#;
+    let script: Script<Instr> = serde_json::from_str(s).unwrap();
+    assert_eq!(format!("{:#}", script), ":start\n10\ndouble\nHALT\n:double\nDUP\n+\nRET\n:end");
+
+    // words that would read back as comments are quoted
+    let script = Script::from(vec![Instr::Word("#x".to_string()), Instr::Word("/*y".to_string())]);
+    assert_eq!(script.to_string(), r##""#x" "/*y""##);
+}
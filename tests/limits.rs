@@ -0,0 +1,372 @@
+extern crate gsm;
+mod common;
+
+use common::{
+    Instr,
+    NullIO
+};
+use gsm::{
+    AppIO,
+    Cause,
+    Clock,
+    Machine,
+    MachineBuilder,
+    Script
+};
+use std::{
+    cell::Cell,
+    io,
+    rc::Rc,
+    thread,
+    time::Duration
+};
+
+// a clock that only moves when it is told to
+#[derive(Default)]
+struct TestClock(Cell<Duration>);
+
+impl TestClock {
+    fn advance(&self, d: Duration) {
+        self.0.set(self.0.get() + d);
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Duration {
+        self.0.get()
+    }
+}
+
+// every read takes a second on the test clock
+struct SlowIO(Rc<TestClock>);
+
+impl AppIO<Instr> for SlowIO {
+    fn open(&self, _m: &mut Machine<Instr>) -> io::Result<()> { Ok(()) }
+    fn read(&self, m: &mut Machine<Instr>) -> io::Result<()> {
+        self.0.advance(Duration::from_secs(1));
+        m.push(Instr::Num(1));
+        Ok(())
+    }
+    fn write(&self, _m: &mut Machine<Instr>) -> io::Result<()> { Ok(()) }
+    fn seek(&self, _m: &mut Machine<Instr>) -> io::Result<()> { Ok(()) }
+    fn close(&self, _m: &mut Machine<Instr>) -> io::Result<()> { Ok(()) }
+}
+
+// counts up forever: 0 1 + 1 + 1 + ...
+fn counter() -> Script<Instr> {
+    Script::from(vec![
+        Instr::Num(0),
+        Instr::Num(1),
+        Instr::Add,
+        Instr::Jmp(1)
+    ])
+}
+
+#[test]
+fn out_of_gas() {
+    let mut machine = MachineBuilder::new()
+        .script(&counter())
+        .fuel(100)
+        .build();
+    let err = machine.execute(&NullIO).unwrap_err();
+
+    match err.cause {
+        Cause::OutOfGas { consumed, remaining } => {
+            assert_eq!(consumed, 100);
+            assert_eq!(remaining, 0);
+        },
+        _ => panic!()
+    }
+    assert_eq!(machine.fuel_consumed(), 100);
+    assert_eq!(machine.fuel_remaining(), Some(0));
+
+    // the first instruction plus 33 trips around the loop
+    assert_eq!(err.ip, 1);
+    assert_eq!(machine.stack().top(), Some(&Instr::Num(33)));
+}
+
+#[test]
+fn out_of_gas_resume() {
+    let mut machine = MachineBuilder::new()
+        .script(&counter())
+        .fuel(10)
+        .build();
+    assert!(machine.execute(&NullIO).is_err());
+    assert_eq!(machine.current_ip(), Some(1));
+
+    // topping up the fuel picks up where the machine left off
+    machine.add_fuel(90);
+    assert!(machine.execute(&NullIO).is_err());
+    assert_eq!(machine.fuel_consumed(), 100);
+    assert_eq!(machine.stack().top(), Some(&Instr::Num(33)));
+}
+
+#[test]
+fn instruction_cost() {
+    let script = Script::from(vec![
+        Instr::Expensive,
+        Instr::Expensive,
+        Instr::Num(1)
+    ]);
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .fuel(15)
+        .build();
+    let err = machine.execute(&NullIO).unwrap_err();
+
+    // the second instruction costs more than what is left
+    assert_eq!(err.ip, 1);
+    assert_eq!(err.instr, Some(Instr::Expensive));
+    match err.cause {
+        Cause::OutOfGas { consumed, remaining } => {
+            assert_eq!(consumed, 10);
+            assert_eq!(remaining, 5);
+        },
+        _ => panic!()
+    }
+}
+
+#[test]
+fn unmetered() {
+    let script = Script::from(vec![
+        Instr::Expensive,
+        Instr::Num(1)
+    ]);
+    let mut machine = Machine::from(script);
+    let result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.size(), 1);
+    assert_eq!(machine.fuel_consumed(), 11);
+    assert_eq!(machine.fuel_remaining(), None);
+}
+
+#[test]
+fn stack_overflow() {
+    // push 1 forever
+    let script = Script::from(vec![
+        Instr::Num(1),
+        Instr::Jmp(0)
+    ]);
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .max_stack(16)
+        .build();
+    let err = machine.execute(&NullIO).unwrap_err();
+
+    match err.cause {
+        Cause::StackOverflow => {},
+        _ => panic!()
+    }
+    assert_eq!(err.ip, 0);
+    assert_eq!(err.instr, Some(Instr::Num(1)));
+    assert_eq!(machine.stack().size(), 16);
+}
+
+#[test]
+fn try_push_overflow() {
+    let script = Script::from(vec![
+        Instr::Num(1),
+        Instr::DupRead
+    ]);
+    let clock = Rc::new(TestClock::default());
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .max_stack(1)
+        .build();
+    let err = machine.execute(&SlowIO(clock.clone())).unwrap_err();
+
+    // the overflow stops the instruction before it reads
+    assert!(matches!(err.cause, Cause::StackOverflow));
+    assert_eq!(err.ip, 1);
+    assert_eq!(err.instr, Some(Instr::DupRead));
+    assert_eq!(clock.now(), Duration::from_secs(0));
+    assert_eq!(machine.stack().size(), 1);
+}
+
+#[test]
+fn return_stack_overflow() {
+    // recurse forever
+    let script = Script::from(vec![
+        Instr::Call(0)
+    ]);
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .max_return_stack(8)
+        .build();
+    let err = machine.execute(&NullIO).unwrap_err();
+
+    match err.cause {
+        Cause::ReturnStackOverflow => {},
+        _ => panic!()
+    }
+    assert_eq!(err.instr, Some(Instr::Call(0)));
+    assert_eq!(machine.return_stack().size(), 8);
+}
+
+#[test]
+fn within_limits() {
+    let script = Script::from(vec![
+        Instr::Num(1),
+        Instr::Num(2),
+        Instr::Add,
+        Instr::Call(4),
+        Instr::Num(3)
+    ]);
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .max_stack(2)
+        .max_return_stack(2)
+        .build();
+    let result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.size(), 2);
+}
+
+#[test]
+fn cancel_before_execute() {
+    let mut machine = Machine::from(counter());
+    let token = machine.cancel_token();
+    token.cancel();
+
+    let err = machine.execute(&NullIO).unwrap_err();
+    match err.cause {
+        Cause::Cancelled => {},
+        _ => panic!()
+    }
+    assert_eq!(err.ip, 0);
+    assert_eq!(err.instr, Some(Instr::Num(0)));
+    assert_eq!(machine.current_ip(), Some(0));
+    assert_eq!(machine.stack().size(), 0);
+
+    // the token stays cancelled until it is reset
+    assert!(machine.execute(&NullIO).is_err());
+    token.reset();
+    assert!(matches!(machine.step(&NullIO), Ok(gsm::Step::Running)));
+}
+
+#[test]
+fn cancel_from_another_thread() {
+    let mut machine = Machine::from(counter());
+    let token = machine.cancel_token();
+    let t = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        token.cancel();
+    });
+
+    // the counter never finishes on its own
+    let err = machine.execute(&NullIO).unwrap_err();
+    t.join().unwrap();
+    match err.cause {
+        Cause::Cancelled => {},
+        _ => panic!()
+    }
+
+    // the machine stopped between instructions with the counter intact
+    match machine.stack().iter().next() {
+        Some(Instr::Num(n)) => assert!(*n > 0),
+        _ => panic!()
+    }
+    assert_eq!(machine.current_ip(), Some(err.ip));
+}
+
+#[test]
+fn deadline_exceeded() {
+    let clock = Rc::new(TestClock::default());
+    clock.advance(Duration::from_secs(100));
+
+    // read forever, each read taking a second
+    let script = Script::from(vec![
+        Instr::Read,
+        Instr::Jmp(0)
+    ]);
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .deadline(Duration::from_millis(2500))
+        .clock(clock.clone())
+        .build();
+    let err = machine.execute(&SlowIO(clock.clone())).unwrap_err();
+
+    match err.cause {
+        Cause::DeadlineExceeded { elapsed } => assert_eq!(elapsed, Duration::from_secs(3)),
+        _ => panic!()
+    }
+
+    // the third read went over the budget and the machine stopped right after
+    assert_eq!(machine.stack().size(), 3);
+    assert_eq!(err.ip, 1);
+    assert_eq!(err.instr, Some(Instr::Jmp(0)));
+}
+
+#[test]
+fn deadline_met() {
+    let clock = Rc::new(TestClock::default());
+    let script = Script::from(vec![
+        Instr::Read,
+        Instr::Read,
+        Instr::Add
+    ]);
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .deadline(Duration::from_secs(2))
+        .clock(clock.clone())
+        .build();
+    let mut result = machine.execute(&SlowIO(clock.clone())).unwrap();
+    assert_eq!(result.pop(), Some(Instr::Num(2)));
+
+    // the budget starts over after a reset
+    machine.reset();
+    clock.advance(Duration::from_secs(60));
+    assert!(machine.execute(&SlowIO(clock.clone())).is_ok());
+}
+
+#[test]
+fn call_and_return() {
+    // call a subroutine that adds 10 twice, then halt before falling into it
+    let script = Script::from(vec![
+        Instr::Num(1),
+        Instr::Call(5),
+        Instr::Call(5),
+        Instr::Halt,
+        Instr::Num(-1),
+        Instr::Num(10),
+        Instr::Add,
+        Instr::Ret
+    ]);
+    let mut machine = Machine::from(script);
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert!(machine.is_halted());
+    assert_eq!(result.size(), 1);
+    assert_eq!(result.pop(), Some(Instr::Num(21)));
+}
+
+#[test]
+fn halt_is_not_an_error() {
+    let script = Script::from(vec![
+        Instr::Num(1),
+        Instr::Halt,
+        Instr::Num(2)
+    ]);
+    let mut machine = Machine::from(script);
+    assert!(matches!(machine.step(&NullIO).unwrap(), gsm::Step::Running));
+    assert!(matches!(machine.step(&NullIO).unwrap(), gsm::Step::Halted));
+
+    // a halted machine stays halted until it is reset
+    assert!(matches!(machine.step(&NullIO).unwrap(), gsm::Step::Halted));
+    assert_eq!(machine.stack().size(), 1);
+    machine.reset();
+    assert!(!machine.is_halted());
+    assert_eq!(machine.execute(&NullIO).unwrap().size(), 1);
+}
+
+#[test]
+fn return_without_call() {
+    let script = Script::from(vec![
+        Instr::Ret
+    ]);
+    let mut machine = Machine::from(script);
+    let err = machine.execute(&NullIO).unwrap_err();
+    assert!(!machine.is_halted());
+    assert_eq!(err.instr, Some(Instr::Ret));
+    match err.cause {
+        Cause::ReturnStackUnderflow => {},
+        _ => panic!()
+    }
+}
@@ -0,0 +1,94 @@
+extern crate gsm;
+use gsm::{
+    literal::{
+        self,
+        Kind
+    },
+    Literal,
+    Literals,
+    Whence
+};
+use semver::Version;
+
+#[test]
+fn literal_ints() {
+    assert_eq!(literal::int("42"), Some(42));
+    assert_eq!(literal::int("+42"), Some(42));
+    assert_eq!(literal::int("-42"), Some(-42));
+    assert_eq!(literal::int("0x1f"), Some(31));
+    assert_eq!(literal::int("-0b101"), Some(-5));
+    assert_eq!(literal::int("0o17"), Some(15));
+    assert_eq!(literal::int("-9223372036854775808"), Some(i64::MIN));
+    assert_eq!(literal::int("9223372036854775808"), None);
+    assert_eq!(literal::int("0x"), None);
+    assert_eq!(literal::int("0x-1"), None);
+    assert_eq!(literal::int("--1"), None);
+    assert_eq!(literal::int("1.5"), None);
+    assert_eq!(literal::int(""), None);
+
+    assert_eq!(literal::uint("18446744073709551615"), Some(u64::MAX));
+    assert_eq!(literal::uint("0xff"), Some(255));
+    assert_eq!(literal::uint("-1"), None);
+}
+
+#[test]
+fn literal_values() {
+    assert_eq!(literal::bool("true"), Some(true));
+    assert_eq!(literal::bool("True"), None);
+    assert_eq!(literal::bytes("0x00ff"), Some(vec![0, 255]));
+    assert_eq!(literal::bytes("0x"), Some(vec![]));
+    assert_eq!(literal::bytes("0xfff"), None);
+    assert_eq!(literal::bytes("00ff"), None);
+    assert_eq!(literal::text("plain"), Some("plain".to_string()));
+    assert_eq!(literal::text(r#""two\twords""#), Some("two\twords".to_string()));
+    assert_eq!(literal::text(r#""a" "b""#), None);
+    assert_eq!(literal::version("1.2.3-beta"), Version::parse("1.2.3-beta").ok());
+    assert_eq!(literal::version("1.2"), None);
+    assert!(literal::mode("rb+").is_some_and(|m| m.read && m.binary && m.plus));
+    assert!(literal::mode("").is_none());
+    assert_eq!(literal::whence("CUR"), Some(Whence::Cur));
+}
+
+#[test]
+fn literal_priority() {
+    let l = Literals::standard();
+    assert_eq!(l.parse("true"), Some(Literal::Bool(true)));
+    assert_eq!(l.parse("1234"), Some(Literal::Int(1234)));
+    assert_eq!(l.parse("0x1234"), Some(Literal::Bytes(vec![0x12, 0x34])));
+    assert_eq!(l.parse("0x12"), Some(Literal::Bytes(vec![0x12])));
+    assert_eq!(l.parse("0x123"), None);
+    assert_eq!(l.parse("0xzz"), None);
+    assert_eq!(l.parse("0b11"), Some(Literal::Int(3)));
+    assert_eq!(l.parse("9223372036854775808"), Some(Literal::Uint(9223372036854775808)));
+    assert_eq!(l.parse("1.0.0").map(|v| v.kind()), Some(Kind::Version));
+    assert_eq!(l.parse("bar"), Some(Literal::Text("bar".to_string())));
+    assert_eq!(l.parse("END"), Some(Literal::Text("END".to_string())));
+
+    // the caller's order wins
+    let mut l = Literals::new();
+    l.kind(Kind::Int).kind(Kind::Bytes).kind(Kind::Whence).kind(Kind::Mode);
+    assert_eq!(l.kinds(), &[Kind::Int, Kind::Bytes, Kind::Whence, Kind::Mode]);
+    assert_eq!(l.parse("0x1234"), Some(Literal::Int(0x1234)));
+    assert_eq!(l.parse("0x00112233445566778899"), Some(Literal::Bytes(hex::decode("00112233445566778899").unwrap())));
+    assert_eq!(l.parse("END"), Some(Literal::Whence(Whence::End)));
+    assert_eq!(l.parse("bar").map(|v| v.kind()), Some(Kind::Mode));
+    assert_eq!(l.parse("text"), None);
+    assert_eq!(Literals::new().parse("1"), None);
+}
+
+#[test]
+fn literal_round_trip() {
+    let l = Literals::standard();
+    for s in &["false", "-7", "0x00ff", "18446744073709551615", "2.0.1", "words"] {
+        let v = l.parse(s).unwrap();
+        assert_eq!(&v.to_string(), s);
+        assert_eq!(l.parse(&v.to_string()), Some(v));
+    }
+
+    // text that looks like another kind is written in quotes
+    for t in &["true", "12", "0x00", "0x123", "1.0.0", "two words", ""] {
+        let v = Literal::Text(t.to_string());
+        assert_eq!(v.to_string(), literal::quote(t));
+        assert_eq!(l.parse(&v.to_string()), Some(v));
+    }
+}
@@ -0,0 +1,123 @@
+#![cfg(feature = "reference")]
+
+extern crate gsm;
+use gsm::{
+    reference::{
+        FileIO,
+        Instr
+    },
+    Cause,
+    Machine,
+    Script,
+    Stack,
+    Verifier
+};
+use std::{
+    env,
+    fs
+};
+
+fn run(s: &str) -> Stack<Instr> {
+    let script = Script::<Instr>::parse(s).unwrap();
+    Machine::from(script).execute(&FileIO::new()).unwrap()
+}
+
+fn top(s: &str) -> Instr {
+    run(s).pop().unwrap()
+}
+
+#[test]
+fn reference_values() {
+    let script = Script::<Instr>::parse("12 -3 true \"two words\" r+ END DUP").unwrap();
+    assert_eq!(script.get(0), Some(Instr::Num(12)));
+    assert_eq!(script.get(1), Some(Instr::Num(-3)));
+    assert_eq!(script.get(2), Some(Instr::Bool(true)));
+    assert_eq!(script.get(3), Some(Instr::Text("two words".to_string())));
+    assert!(matches!(script.get(4), Some(Instr::Mode(m)) if m.read && m.plus));
+    assert_eq!(script.get(5), Some(Instr::Whence(gsm::Whence::End)));
+    assert_eq!(script.get(6), Some(Instr::Dup));
+    assert_eq!(script.to_string(), "12 -3 true \"two words\" rw+ END DUP");
+
+    let script = Script::<Instr>::parse("0x10 \"\"").unwrap();
+    assert_eq!(script.get(0), Some(Instr::Num(16)));
+    assert_eq!(script.get(1), Some(Instr::Text(String::new())));
+}
+
+#[test]
+fn reference_text_round_trip() {
+    // text that would be read as a keyword or another value is quoted
+    let text = ["IF", "bar", "1", "true", "END", "@l", "two words", "\"", ""];
+    let script = Script::from(text.iter().map(|t| Instr::Text(t.to_string())).collect::<Vec<_>>());
+    let s = script.to_string();
+    assert_eq!(s, r#""IF" "bar" "1" "true" "END" "@l" "two words" "\"" """#);
+    assert_eq!(Script::<Instr>::parse(&s).unwrap(), script);
+
+    let script = Script::<Instr>::parse("plain \"plain\" IF \"IF\" FI").unwrap();
+    assert_eq!(script.get(0), Some(Instr::Text("plain".to_string())));
+    assert_eq!(script.get(1), Some(Instr::Text("plain".to_string())));
+    assert_eq!(script.get(2), Some(Instr::If));
+    assert_eq!(script.get(3), Some(Instr::Text("IF".to_string())));
+    assert_eq!(script.to_string(), "plain plain IF \"IF\" FI");
+}
+
+#[test]
+fn reference_arithmetic() {
+    assert_eq!(top("2 3 + 4 *"), Instr::Num(20));
+    assert_eq!(top("7 2 - 2 /"), Instr::Num(2));
+    assert_eq!(top("7 3 %"), Instr::Num(1));
+    assert_eq!(top("1 2 SWAP -"), Instr::Num(1));
+    assert_eq!(top("1 2 OVER + +"), Instr::Num(4));
+    assert_eq!(top("1 2 <"), Instr::Bool(true));
+    assert_eq!(top("\"a\" \"a\" = NOT"), Instr::Bool(false));
+
+    let script = Script::<Instr>::parse("1 0 /").unwrap();
+    let e = Machine::from(script).execute(&FileIO::new()).unwrap_err();
+    assert_eq!(e.ip, 2);
+    assert!(matches!(e.cause, Cause::Other(_)));
+}
+
+#[test]
+fn reference_control() {
+    assert_eq!(top("1 2 < IF 10 ELSE 20 FI"), Instr::Num(10));
+    assert_eq!(top("false IF 10 ELSE 20 FI"), Instr::Num(20));
+
+    // sum 1 to 4 with a loop and a subroutine
+    let s = run("0 4 :loop @add CALL 1 - DUP @done JZ @loop JMP :add SWAP OVER + SWAP RET :done DROP");
+    assert_eq!(s.size(), 1);
+    assert_eq!(s.top(), Some(&Instr::Num(10)));
+
+    assert_eq!(run("1 HALT 2").size(), 1);
+}
+
+#[test]
+fn reference_file_io() {
+    let path = env::temp_dir().join(format!("gsm-reference-{}.txt", std::process::id()));
+    let p = path.to_str().unwrap();
+    let s = format!("\"{p}\" w OPEN \"hello world\" WRITE CLOSE \"{p}\" r OPEN 6 START SEEK 5 READ SWAP CLOSE", p = p);
+    let mut result = run(&s);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(result.size(), 1);
+    assert_eq!(result.pop(), Some(Instr::Text("world".to_string())));
+}
+
+#[test]
+fn reference_print() {
+    let io = FileIO::with_output(Vec::new());
+    let script = Script::<Instr>::parse("\"two words\" PRINT 1 2 + PRINT \"IF\" PRINT").unwrap();
+    let result = Machine::from(script).execute(&io).unwrap();
+    assert_eq!(result.size(), 0);
+    assert_eq!(String::from_utf8(io.into_output()).unwrap(), "two words\n3\nIF\n");
+
+    // printing with nothing on the stack fails like any other io
+    let script = Script::<Instr>::parse("PRINT").unwrap();
+    let e = Machine::from(script).execute(&FileIO::new()).unwrap_err();
+    assert!(matches!(e.cause, Cause::Io(_)));
+}
+
+#[test]
+fn reference_bytecode() {
+    let script = Script::<Instr>::parse("1 true \"x y\" a CUR :l IF ELSE FI @l JMP PRINT").unwrap();
+    let back = Script::<Instr>::from_bytecode(&script.to_bytecode()).unwrap();
+    assert_eq!(back.to_string(), script.to_string());
+    assert!(Verifier::<Instr>::new().verify(&back).is_ok());
+}
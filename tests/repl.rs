@@ -0,0 +1,110 @@
+#![cfg(feature = "reference")]
+
+extern crate gsm;
+use gsm::{
+    reference::{
+        FileIO,
+        Instr
+    },
+    Cause,
+    Machine,
+    MachineBuilder,
+    Repl,
+    ReplError,
+    Reply,
+    Script,
+    Stack
+};
+use std::{
+    env,
+    fs
+};
+
+fn stack(r: Result<Reply<Instr>, ReplError<Instr>>) -> Vec<Instr> {
+    match r {
+        Ok(Reply::Stack(d)) => d.iter().cloned().collect(),
+        _ => panic!()
+    }
+}
+
+#[test]
+fn machine_load() {
+    let io = FileIO::new();
+    let mut m = Machine::from(Script::<Instr>::parse("1 2").unwrap());
+    m.execute(&io).unwrap();
+    m.load(&Script::parse("+").unwrap());
+    let d = m.execute(&io).unwrap();
+    assert_eq!(d.iter().collect::<Vec<_>>(), vec![&Instr::Num(3)]);
+    assert_eq!(m.fuel_consumed(), 3);
+
+    m.set_stack(Stack::from(vec![Instr::Num(7)]));
+    m.load(&Script::parse("DUP").unwrap());
+    assert_eq!(m.execute(&io).unwrap().size(), 2);
+}
+
+#[test]
+fn repl_lines() {
+    let io = FileIO::new();
+    let mut r = Repl::<Instr>::default();
+    assert_eq!(stack(r.eval("1 2", &io)), vec![Instr::Num(1), Instr::Num(2)]);
+    assert_eq!(stack(r.eval("+ # add them", &io)), vec![Instr::Num(3)]);
+
+    // a failing line leaves the stack alone
+    match r.eval("\"x\" +", &io) {
+        Err(ReplError::Machine(e)) => assert!(matches!(e.cause, Cause::TypeMismatch)),
+        _ => panic!()
+    }
+    assert!(matches!(r.eval("\"x", &io), Err(ReplError::Parse(_))));
+    assert_eq!(stack(r.eval(".stack", &io)), vec![Instr::Num(3)]);
+
+    // each line has its own labels
+    assert_eq!(stack(r.eval(":top 1 + DUP 10 = @top JZ", &io)), vec![Instr::Num(10)]);
+
+    assert_eq!(stack(r.eval(".undo", &io)), vec![Instr::Num(3)]);
+    assert_eq!(stack(r.eval(".undo", &io)), vec![Instr::Num(1), Instr::Num(2)]);
+    assert_eq!(stack(r.eval(".undo", &io)), vec![]);
+    assert!(matches!(r.eval(".undo", &io), Err(ReplError::NothingToUndo)));
+
+    r.eval("5 HALT", &io).unwrap();
+    assert_eq!(stack(r.eval("DUP", &io)), vec![Instr::Num(5), Instr::Num(5)]);
+    assert_eq!(stack(r.eval(".reset", &io)), vec![]);
+    assert!(matches!(r.eval(".undo", &io), Err(ReplError::NothingToUndo)));
+    assert!(matches!(r.eval(".help", &io), Ok(Reply::Help(_))));
+    assert!(matches!(r.eval(" .quit ", &io), Ok(Reply::Quit)));
+}
+
+#[test]
+fn repl_load() {
+    let io = FileIO::new();
+    let path = env::temp_dir().join(format!("gsm-repl-{}.gsm", std::process::id()));
+    fs::write(&path, "# a library\n10 20\n").unwrap();
+    let mut r = Repl::<Instr>::default();
+    r.eval("1", &io).unwrap();
+    let line = format!(".load {}", path.display());
+    assert_eq!(stack(r.eval(&line, &io)), vec![Instr::Num(1), Instr::Num(10), Instr::Num(20)]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(matches!(r.eval(&line, &io), Err(ReplError::Io(_))));
+    assert!(matches!(r.eval(".load", &io), Err(ReplError::Usage(_))));
+    assert_eq!(stack(r.eval(".undo", &io)), vec![Instr::Num(1)]);
+}
+
+#[test]
+fn repl_interact() {
+    let mut b = MachineBuilder::new();
+    b.fuel(4);
+    let mut r = Repl::<Instr>::new(b.build());
+    let input = "1 2\n+\n.undo\n3 4 5\n.quit\n6\n";
+    let mut out = Vec::new();
+    r.interact(input.as_bytes(), &mut out, &FileIO::new()).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+        "> 2\n1\n> 3\n> 2\n1\n> error: '4' at 1: out of gas (4 consumed, 0 remaining)\n> ");
+    assert_eq!(r.stack().size(), 2);
+
+    // what scripts print goes to the FileIO's output, apart from the replies
+    let io = FileIO::with_output(Vec::new());
+    let mut out = Vec::new();
+    Repl::<Instr>::default().interact("\"hi\" PRINT 1\n".as_bytes(), &mut out, &io).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "> 1\n> \n");
+    assert_eq!(io.into_output(), b"hi\n");
+}
@@ -0,0 +1,139 @@
+extern crate gsm;
+mod common;
+
+use common::NullIO;
+use gsm::{
+    AppIO,
+    Block,
+    Cause,
+    Instruction,
+    Machine,
+    MachineError,
+    Script
+};
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+// an instruction set that serde sees as a tagged enum rather than strings,
+// which the shared one can't be
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", content = "arg")]
+enum Instr {
+    Push(i64),
+    Add,
+    When,
+    End
+}
+
+impl std::fmt::Display for Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Instruction<Instr> for Instr {
+    fn execute(&self, ip: usize, m: &mut Machine<Instr>, _io: &dyn AppIO<Instr>) -> Result<(), MachineError<Instr>> {
+        match self {
+            Instr::Push(_) => m.push(self.clone()),
+            Instr::Add => {
+                match (m.pop(), m.pop()) {
+                    (Some(Instr::Push(r)), Some(Instr::Push(l))) => m.push(Instr::Push(l + r)),
+                    _ => return Err(Cause::TypeMismatch.into())
+                }
+            },
+            Instr::When => {
+                // skip to the end of the block if the top of the stack is zero
+                if let Some(Instr::Push(0)) = m.pop() {
+                    let info = m.block(ip).ok_or(Cause::InvalidInstruction)?;
+                    m.next(info.close);
+                    return Ok(());
+                }
+            },
+            Instr::End => {}
+        }
+        m.next(ip);
+        Ok(())
+    }
+
+    fn block(&self) -> Option<Block> {
+        match self {
+            Instr::When => Some(Block::Open),
+            Instr::End => Some(Block::Close),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Program {
+    name: String,
+    #[serde(with = "gsm::script::seq")]
+    script: Script<Instr>
+}
+
+fn program() -> Program {
+    Program {
+        name: "add".to_string(),
+        script: Script::from(vec![
+            Instr::Push(1),
+            Instr::Push(2),
+            Instr::Add,
+            Instr::Push(1),
+            Instr::When,
+            Instr::Push(3),
+            Instr::Add,
+            Instr::End
+        ])
+    }
+}
+
+#[test]
+fn sequence_json() {
+    let p = program();
+    let s = serde_json::to_string(&p).unwrap();
+    assert!(s.starts_with(r#"{"name":"add","script":[{"op":"Push","arg":1},{"op":"Push","arg":2},{"op":"Add"}"#));
+
+    let p: Program = serde_json::from_str(&s).unwrap();
+    let mut machine = Machine::from(p.script);
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.pop(), Some(Instr::Push(6)));
+}
+
+#[test]
+fn sequence_cbor() {
+    let p = program();
+    let b = serde_cbor::to_vec(&p).unwrap();
+    let q: Program = serde_cbor::from_slice(&b).unwrap();
+    assert_eq!(q.script, p.script);
+    assert_eq!(q.script.blocks(), p.script.blocks());
+}
+
+#[test]
+fn sequence_cbor_huge_length() {
+    // array headers claiming 2^64 - 1 and 2^32 - 1 elements with none there
+    let b = [0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+    let r: Result<Script<Instr>, _> = gsm::script::seq::deserialize(&mut serde_cbor::Deserializer::from_slice(&b));
+    assert!(r.is_err());
+    let b = [0x9a, 0xff, 0xff, 0xff, 0xff];
+    let r: Result<Script<Instr>, _> = gsm::script::seq::deserialize(&mut serde_cbor::Deserializer::from_slice(&b));
+    assert!(r.is_err());
+}
+
+#[test]
+fn sequence_any() {
+    // a bare Script is only ever a string, script::any takes either
+    let s = r#"[{"op":"Push","arg":4},{"op":"When"},{"op":"Push","arg":5},{"op":"End"}]"#;
+    assert!(serde_json::from_str::<Script<Instr>>(s).is_err());
+    let script: Script<Instr> = gsm::script::any::deserialize(&mut serde_json::Deserializer::from_str(s)).unwrap();
+    assert_eq!(script.len(), 4);
+    assert_eq!(script.blocks().unwrap().get(1).map(|b| b.close), Some(3));
+}
+
+#[test]
+fn sequence_malformed_blocks() {
+    let s = r#"{"name":"bad","script":[{"op":"When"},{"op":"Push","arg":5}]}"#;
+    let err = serde_json::from_str::<Program>(s).unwrap_err();
+    assert!(err.to_string().contains("block opened at 0 is never closed"));
+}
@@ -1,9 +1,28 @@
+#![allow(clippy::unnecessary_cast)]
 extern crate gsm;
 use gsm::{
     AppIO,
+    Block,
+    BlockError,
+    Cause,
+    Effect,
+    EffectError,
+    FlowError,
     Instruction,
+    JsonTracer,
     Machine,
-    Script
+    MachineBuilder,
+    MachineError,
+    Optimize,
+    Optimizer,
+    Script,
+    Signature,
+    Stack,
+    StackEffect,
+    Step,
+    Type,
+    Typed,
+    TypeError
 };
 use serde::{
     de,
@@ -12,8 +31,10 @@ use serde::{
     Deserializer
 };
 use std::{
+    cell::RefCell,
     fmt,
-    io
+    io,
+    rc::Rc
 };
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -35,6 +56,7 @@ impl<'de> de::Visitor<'de> for InstrVisitor {
         write!(f, "Instr token")
     }
 
+    #[allow(clippy::needless_return)]
     fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
         match v {
             "+" => Ok(Instr::Add),
@@ -76,58 +98,6 @@ impl fmt::Display for Instr {
     }
 }
 
-struct IfMatch {
-    ifi: usize,
-    elsei: Option<usize>,
-    fii: usize
-}
-
-fn find_matching_elsefi(m: &Machine<Instr>, i: usize) -> Option<IfMatch> {
-    let mut ret = IfMatch { ifi: i, elsei: None, fii: 0 };
-    let mut ip = ret.ifi + 1;
-    loop {
-        match m.geti(ip) {
-            // skip over instructions that aren't if/else/fi
-            Some(Instr::Add) |
-            Some(Instr::Num(_)) |
-            Some(Instr::Boolean(_)) => {
-                ip += 1;
-            },
-
-            Some(Instr::If) => {
-                // this is an inner 'IF' that we need to find the end of.
-                let im = match find_matching_elsefi(m, ip) {
-                    Some(inner) => inner,
-                    None => return None
-                };
-
-                // skip to the index just after the closing 'FI'
-                ip = im.fii + 1;
-            },
-
-            Some(Instr::Else) => {
-                // this is an 'ELSE' to our starting 'IF' so we just record the
-                // index in the result and move on.
-                ret.elsei = Some(ip);
-                ip += 1;
-            },
-
-            Some(Instr::Fi) => {
-                // we found our matching 'FI' so return the result
-                ret.fii = ip;
-                return Some(ret);
-            },
-
-            // if we don't get an instruction we've reached the end of the
-            // script without finding the matching 'FI' so return None to
-            // signal the error.
-            None => {
-                return None;
-            }
-        }
-    }
-}
-
 struct NullIO;
 
 impl AppIO<Instr> for NullIO {
@@ -140,90 +110,147 @@ impl AppIO<Instr> for NullIO {
 
 impl Instruction<Instr> for Instr {
 
-    fn execute(&self, ip: usize, m: &mut Machine<Instr>, _io: &dyn AppIO<Instr>) {
+    fn execute(&self, ip: usize, m: &mut Machine<Instr>, _io: &dyn AppIO<Instr>) -> Result<(), MachineError<Instr>> {
         match self {
             Instr::Add => {
-                if let Some(Instr::Num(r)) = m.pop() {
-                    if let Some(Instr::Num(l)) = m.pop() {
+                match (m.pop(), m.pop()) {
+                    (Some(Instr::Num(r)), Some(Instr::Num(l))) => {
                         m.push(Instr::Num(l + r));
-                        m.pushr(ip + 1);
-                        return;
-                    }
+                        m.next(ip);
+                        Ok(())
+                    },
+                    (Some(_), Some(_)) => Err(Cause::TypeMismatch.into()),
+                    _ => Err(Cause::StackUnderflow.into())
                 }
-                panic!();
             },
             Instr::If => {
-                // find the location of the matching 'ELSE' if any and 'FI'
-                if let Some(ifm) = find_matching_elsefi(m, ip) {
-                    // get the Boolean from the stack
-                    if let Some(Instr::Boolean(b)) = m.pop() {
-                        if b {
-                            // the boolean is true so continue with the code that is
-                            // between this if and it's matching 'ELSE'
-
-                            // first record where we need to go after this block
-                            m.pushr(ifm.fii + 1);
-
-                            // then tell the machine the correct next instruction
-                            m.pushr(ip + 1);
-                            return;
-                        } else {
-                            // the boolean is false so skip to the instruction after
-                            // the 'ELSE' if there is one, otherwise skip to after the
-                            // 'FI'
-                            let next_ip = match ifm.elsei {
-                                Some(i) => {
-                                    // we're executing the 'ELSE' block so we need to
-                                    // push a frame with the correct next instruction
-                                    m.pushr(ifm.fii + 1);
-
-                                    // set the next instruction pointer to the
-                                    // instruction after the 'ELSE'
-                                    i + 1
-                                },
-
-                                // No 'ELSE' clause so just skip to the instruction
-                                // after the 'FI'. There is no need to record a frame.
-                                None => ifm.fii + 1
-                            };
-
-                            m.pushr(next_ip);
-                            return;
-                        }
+                // look up the location of the matching 'ELSE' if any and 'FI'
+                let ifm = match m.block(ip) {
+                    Some(ifm) => ifm,
+                    None => return Err(Cause::InvalidInstruction.into())
+                };
+
+                // get the Boolean from the stack
+                let b = match m.pop() {
+                    Some(Instr::Boolean(b)) => b,
+                    Some(_) => return Err(Cause::TypeMismatch.into()),
+                    None => return Err(Cause::StackUnderflow.into())
+                };
+
+                if b {
+                    // the boolean is true so continue with the code that is
+                    // between this if and it's matching 'ELSE' and come back
+                    // to the instruction after the 'FI' when done
+                    m.call(ip + 1, ifm.close + 1);
+                } else {
+                    // the boolean is false so skip to the instruction after
+                    // the 'ELSE' if there is one, otherwise skip to after the
+                    // 'FI'
+                    match ifm.split {
+                        // we're executing the 'ELSE' block so we need to
+                        // come back to the instruction after the 'FI'
+                        Some(i) => m.call(i + 1, ifm.close + 1),
+
+                        // No 'ELSE' clause so just skip to the instruction
+                        // after the 'FI'. There is no need to record a frame.
+                        None => m.jump(ifm.close + 1)
                     }
                 }
-                panic!();
+                Ok(())
             },
             Instr::Else => {
                 // we see an 'ELSE' so this can only be because we previously
                 // encoutered in 'IF' and the boolean was true and the
                 // if/else/fi block had an else. the right thing to do here is
-                // to pop the frame from the machine and skip to the next
-                // instruction pointer.
-                if let Some(next_ip) = m.popr() {
-                    m.pushr(next_ip);
-                    return;
-                }
-                panic!();
+                // to return to the instruction after the 'FI'.
+                m.ret()
             }
             Instr::Fi => {
-                // we finished executing an 'IF' or 'ELSE' block so pop the
-                // frame and continue
-                if let Some(next_ip) = m.popr() {
-                    m.pushr(next_ip);
-                    return;
-                }
-                panic!();
+                // we finished executing an 'IF' or 'ELSE' block so return
+                // to the instruction after it
+                m.ret()
             },
             Instr::Num(_) |
             Instr::Boolean(_) => {
                 // push the value onto the stack and keep going
                 m.push(*self);
-                m.pushr(ip + 1);
-                return;
+                m.next(ip);
+                Ok(())
             }
         }
     }
+
+    fn block(&self) -> Option<Block> {
+        match self {
+            Instr::If => Some(Block::Open),
+            Instr::Else => Some(Block::Split),
+            Instr::Fi => Some(Block::Close),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    Num,
+    Bool
+}
+
+impl Typed for Instr {
+    type Type = Kind;
+
+    fn signature(&self) -> Signature<Kind> {
+        match self {
+            Instr::Add => Signature::new(vec![Type::Is(Kind::Num), Type::Is(Kind::Num)], vec![Type::Is(Kind::Num)]),
+            Instr::If => Signature::new(vec![Type::Is(Kind::Bool)], vec![]),
+            Instr::Else | Instr::Fi => Signature::new(vec![], vec![]),
+            Instr::Num(_) => Signature::new(vec![], vec![Type::Is(Kind::Num)]),
+            Instr::Boolean(_) => Signature::new(vec![], vec![Type::Is(Kind::Bool)])
+        }
+    }
+}
+
+impl Optimize for Instr {
+    fn is_constant(&self) -> bool {
+        matches!(self, Instr::Num(_) | Instr::Boolean(_))
+    }
+
+    fn fold(&self, args: &[Instr]) -> Option<Vec<Instr>> {
+        match (self, args) {
+            (Instr::Add, [Instr::Num(l), Instr::Num(r)]) => Some(vec![Instr::Num(l + r)]),
+            _ => None
+        }
+    }
+
+    fn decide(&self, cond: &Instr) -> Option<bool> {
+        match (self, cond) {
+            (Instr::If, Instr::Boolean(b)) => Some(*b),
+            _ => None
+        }
+    }
+
+    fn peephole(window: &[Instr]) -> Option<(usize, Vec<Instr>)> {
+        // adding zero does nothing
+        match window {
+            [Instr::Num(0), Instr::Add, ..] => Some((2, vec![])),
+            _ => None
+        }
+    }
+
+    fn retarget(&self, _map: &dyn Fn(usize) -> usize) -> Instr {
+        *self
+    }
+}
+
+impl Effect for Instr {
+    fn effect(&self) -> StackEffect {
+        match self {
+            Instr::Add => StackEffect::new(2, 1),
+            Instr::If => StackEffect::new(1, 0),
+            Instr::Else | Instr::Fi => StackEffect::new(0, 0),
+            Instr::Num(_) | Instr::Boolean(_) => StackEffect::new(0, 1)
+        }
+    }
 }
 
 
@@ -439,6 +466,239 @@ fn nested_branching_3() {
     }
 }
 
+#[test]
+fn add_type_mismatch() {
+    let script = Script::from(vec![
+        Instr::Num(3),
+        Instr::Boolean(true),
+        Instr::Add
+    ]);
+    let mut machine = Machine::from(script);
+    let err = machine.execute(&NullIO).unwrap_err();
+
+    // the error should name the 'ADD' instruction and its location
+    assert_eq!(err.ip, 2);
+    assert_eq!(err.instr, Some(Instr::Add));
+    match err.cause {
+        Cause::TypeMismatch => {},
+        _ => panic!()
+    }
+}
+
+#[test]
+fn add_stack_underflow() {
+    let script = Script::from(vec![
+        Instr::Num(3),
+        Instr::Add
+    ]);
+    let mut machine = Machine::from(script);
+    let err = machine.execute(&NullIO).unwrap_err();
+    assert_eq!(err.ip, 1);
+    match err.cause {
+        Cause::StackUnderflow => {},
+        _ => panic!()
+    }
+    assert_eq!(format!("{}", err), "'+' at 1: stack underflow");
+}
+
+#[test]
+fn single_step() {
+    let script = Script::from(vec![
+        Instr::Num(3),
+        Instr::Num(5),
+        Instr::Add
+    ]);
+    let mut machine = Machine::from(script);
+
+    // push the 3
+    assert_eq!(machine.current_ip(), Some(0));
+    assert!(matches!(machine.step(&NullIO).unwrap(), Step::Running));
+    assert_eq!(machine.stack().size(), 1);
+    assert_eq!(machine.stack().top(), Some(&Instr::Num(3)));
+
+    // push the 5
+    assert_eq!(machine.current_ip(), Some(1));
+    assert!(matches!(machine.step(&NullIO).unwrap(), Step::Running));
+    assert_eq!(machine.stack().size(), 2);
+    assert_eq!(machine.stack().top(), Some(&Instr::Num(5)));
+
+    // add them
+    assert_eq!(machine.current_ip(), Some(2));
+    assert!(matches!(machine.step(&NullIO).unwrap(), Step::Running));
+    assert_eq!(machine.stack().size(), 1);
+    assert_eq!(machine.stack().top(), Some(&Instr::Num(8)));
+
+    // the script is done and stays done
+    for _ in 0..2 {
+        match machine.step(&NullIO).unwrap() {
+            Step::Finished(mut result) => {
+                assert_eq!(result.size(), 1);
+                assert_eq!(result.pop(), Some(Instr::Num(8)));
+            },
+            _ => panic!()
+        }
+    }
+}
+
+#[test]
+fn single_step_branching() {
+    let script = Script::from(vec![
+        Instr::Boolean(false),
+        Instr::If,
+            Instr::Num(1),
+        Instr::Else,
+            Instr::Num(2),
+        Instr::Fi
+    ]);
+    let mut machine = Machine::from(script);
+
+    // record every instruction pointer the machine visits
+    let mut ips = Vec::new();
+    while let Some(ip) = machine.current_ip() {
+        ips.push(ip);
+        if let Step::Finished(_) = machine.step(&NullIO).unwrap() {
+            break;
+        }
+    }
+    assert_eq!(ips, vec![0, 1, 4, 5, 6]);
+}
+
+#[test]
+fn single_step_underflow() {
+    let script = Script::from(vec![
+        Instr::Num(1),
+        Instr::Num(2)
+    ]);
+    let mut machine = Machine::from(script);
+    assert!(matches!(machine.step(&NullIO).unwrap(), Step::Running));
+
+    // take away the next frame so the machine has nowhere to go
+    assert_eq!(machine.popr(), Some(1));
+    assert_eq!(machine.current_ip(), None);
+    let err = machine.step(&NullIO).unwrap_err();
+    assert_eq!(err.ip, 0);
+    match err.cause {
+        Cause::ReturnStackUnderflow => {},
+        _ => panic!()
+    }
+
+    // the data stack is left as it was
+    assert_eq!(machine.stack().size(), 1);
+}
+
+#[test]
+fn json_tracing() {
+    let script = Script::from(vec![
+        Instr::Num(3),
+        Instr::Num(5),
+        Instr::Add
+    ]);
+    let tracer = Rc::new(RefCell::new(JsonTracer::new(Vec::new())));
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .tracer(tracer.clone())
+        .build();
+    machine.execute(&NullIO).unwrap();
+
+    // one line before and one line after each instruction
+    let t = tracer.borrow();
+    let out = String::from_utf8(t.get_ref().clone()).unwrap();
+    let lines: Vec<serde_json::Value> = out.lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 6);
+
+    assert_eq!(lines[4], serde_json::json!({
+        "event": "before",
+        "ip": 2,
+        "instr": "+",
+        "stack": ["3", "5"],
+        "rstack": []
+    }));
+    assert_eq!(lines[5], serde_json::json!({
+        "event": "after",
+        "ip": 2,
+        "instr": "+",
+        "stack": ["8"],
+        "rstack": [3]
+    }));
+}
+
+#[test]
+fn block_table() {
+    let script = Script::from(vec![
+        Instr::Boolean(true),
+        Instr::If,
+            Instr::Boolean(false),
+            Instr::If,
+                Instr::Num(3),
+            Instr::Fi,
+        Instr::Else,
+            Instr::Num(2),
+        Instr::Fi
+    ]);
+    let blocks = script.blocks().unwrap();
+
+    // the outer block is the same from all of its instructions
+    let outer = blocks.get(1).unwrap();
+    assert_eq!(outer.open, 1);
+    assert_eq!(outer.split, Some(6));
+    assert_eq!(outer.close, 8);
+    assert_eq!(blocks.get(6), Some(outer));
+    assert_eq!(blocks.get(8), Some(outer));
+
+    let inner = blocks.get(3).unwrap();
+    assert_eq!(inner.split, None);
+    assert_eq!(inner.close, 5);
+
+    // everything else isn't part of a block
+    assert_eq!(blocks.get(0), None);
+    assert_eq!(blocks.get(4), None);
+    assert_eq!(blocks.get(9), None);
+
+    assert_eq!(blocks.depth(0), 0);
+    assert_eq!(blocks.depth(3), 1);
+    assert_eq!(blocks.depth(4), 2);
+    assert_eq!(blocks.depth(6), 0);
+    assert_eq!(blocks.depth(7), 1);
+}
+
+#[test]
+fn malformed_blocks() {
+    let unclosed = Script::from(vec![Instr::Boolean(true), Instr::If, Instr::Num(1)]);
+    assert_eq!(unclosed.blocks().unwrap_err(), &BlockError::Unclosed(1));
+
+    let unopened = Script::from(vec![Instr::Num(1), Instr::Fi]);
+    assert_eq!(unopened.blocks().unwrap_err(), &BlockError::Unopened(1));
+
+    let split = Script::from(vec![
+        Instr::Boolean(true),
+        Instr::If,
+        Instr::Else,
+        Instr::Else,
+        Instr::Fi
+    ]);
+    assert_eq!(split.blocks().unwrap_err(), &BlockError::DuplicateSplit(3));
+
+    // a machine refuses to run any of it
+    let mut machine = Machine::from(unopened);
+    let err = machine.execute(&NullIO).unwrap_err();
+    assert_eq!(err.ip, 1);
+    assert_eq!(err.instr, Some(Instr::Fi));
+    match err.cause {
+        Cause::Block(BlockError::Unopened(1)) => {},
+        _ => panic!()
+    }
+    assert_eq!(machine.stack().size(), 0);
+}
+
+#[test]
+fn deserialization_malformed_blocks() {
+    let s = r#""true IF 1 ELSE 2""#;
+    let err = serde_json::from_str::<Script<Instr>>(s).unwrap_err();
+    assert!(err.to_string().contains("block opened at 1 is never closed"));
+}
+
 #[test]
 fn serialization_json() {
     // construct a simple if/else/fi script and load it into the machine
@@ -617,3 +877,200 @@ fn deserialization_cbor() {
         _ => panic!()
     }
 }
+
+#[test]
+fn deserialization_comments() {
+    let s = r##""# add two numbers\n1 2 + /* keep the sum */ true\nIF /* a block\ncomment */ 3 + FI # done""##;
+    let script: Script<Instr> = serde_json::from_str(s).unwrap();
+    assert_eq!(script.len(), 8);
+    assert_eq!(script.to_string(), "1 2 + true IF 3 + FI");
+
+    let mut machine = Machine::from(script);
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.pop(), Some(Instr::Num(6)));
+
+    let unterminated = r#""1 2 /* never closed +""#;
+    assert!(serde_json::from_str::<Script<Instr>>(unterminated).is_err());
+}
+
+#[test]
+fn deserialization_quoted_plain() {
+    // an instruction set that doesn't read quoted tokens itself gets them
+    // without their quotes
+    let script = Script::<Instr>::parse(r#""1" "2" "+" "true" "IF" 3 + "FI""#).unwrap();
+    assert_eq!(script.to_string(), "1 2 + true IF 3 + FI");
+
+    let mut machine = Machine::from(script);
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.pop(), Some(Instr::Num(6)));
+}
+
+#[test]
+fn pretty_printing() {
+    let s = r#""true IF false IF 1 ELSE 2 FI ELSE 3 FI 4 +""#;
+    let script: Script<Instr> = serde_json::from_str(s).unwrap();
+    let pretty = format!("{:#}", script);
+    assert_eq!(pretty, "true\nIF\n    false\n    IF\n        1\n    ELSE\n        2\n    FI\nELSE\n    3\nFI\n4\n+");
+
+    // the pretty form reads back as the same script
+    let again: Script<Instr> = serde_json::from_str(&serde_json::to_string(&pretty).unwrap()).unwrap();
+    assert_eq!(again.to_string(), script.to_string());
+}
+
+#[test]
+fn parse_error_position() {
+    let err = Script::<Instr>::parse("1 2 +\n  true IF bogus FI").unwrap_err();
+    assert_eq!(err.index, Some(5));
+    assert_eq!(err.offset, 16);
+    assert_eq!(err.line, 2);
+    assert_eq!(err.column, 11);
+    assert_eq!(err.token, "bogus");
+    assert_eq!(err.to_string(), "line 2, column 11: failed to parse 'bogus' (token 5 'bogus')");
+
+    // badly nested blocks are reported at the token that breaks them
+    let err = Script::<Instr>::parse("true IF 1 ELSE 2 ELSE 3 FI").unwrap_err();
+    assert_eq!(err.index, Some(5));
+    assert_eq!(err.token, "ELSE");
+
+    // text that can't be split into tokens has no token index
+    let err = Script::<Instr>::parse("1 \"open").unwrap_err();
+    assert_eq!(err.index, None);
+    assert_eq!(err.column, 3);
+    assert_eq!(err.token, "\"open");
+
+    // the position is part of the serde error too
+    let err = serde_json::from_str::<Script<Instr>>(r#""1 foo +""#).unwrap_err();
+    assert!(err.to_string().contains("line 1, column 3: failed to parse 'foo'"));
+}
+
+#[test]
+fn parse_all_errors() {
+    let errs = Script::<Instr>::parse_all("foo 1 +\nbar IF\n  2 baz FI").unwrap_err();
+    let at: Vec<(Option<usize>, usize, usize)> = errs.iter().map(|e| (e.index, e.line, e.column)).collect();
+    assert_eq!(at, vec![(Some(0), 1, 1), (Some(3), 2, 1), (Some(6), 3, 5)]);
+
+    let script = Script::<Instr>::parse_all("1 2 +").unwrap();
+    assert_eq!(script.len(), 3);
+}
+
+#[test]
+fn stack_effects() {
+    let script = Script::<Instr>::parse("1 true IF 2 ELSE false IF 3 ELSE 4 FI FI +").unwrap();
+    let report = gsm::effect::analyze(&script).unwrap();
+    assert_eq!(report.max_depth, 2);
+    assert_eq!(report.depths[0], Some(0));
+    assert_eq!(report.depths[3], Some(1));
+    assert_eq!(report.depths[12], Some(2));
+    assert_eq!(report.depths[13], Some(1));
+
+    let mut machine = Machine::from(script);
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.pop(), Some(Instr::Num(3)));
+}
+
+#[test]
+fn stack_effect_errors() {
+    let script = Script::<Instr>::parse("1 +").unwrap();
+    let err = gsm::effect::analyze(&script).unwrap_err();
+    assert_eq!(err, EffectError::Underflow { index: 1, depth: 1, takes: 2 });
+    assert_eq!(err.to_string(), "instruction at 1 takes 2 items from a stack of 1");
+
+    // the arms of a branch have to leave the same number of items behind
+    let script = Script::<Instr>::parse("true IF 1 ELSE 2 3 FI").unwrap();
+    match gsm::effect::analyze(&script) {
+        Err(EffectError::Height { index: 7, .. }) => {},
+        r => panic!("{:?}", r)
+    }
+
+    // as does skipping a block with no 'ELSE'
+    let script = Script::<Instr>::parse("true IF 1 FI").unwrap();
+    assert_eq!(gsm::effect::analyze(&script).unwrap_err().index(), 4);
+}
+
+#[test]
+fn type_check_branches() {
+    let script = Script::<Instr>::parse("1 true IF 2 ELSE 3 FI +").unwrap();
+    gsm::typed::check(&script).unwrap();
+
+    // one arm leaves a number and the other a boolean
+    let script = Script::<Instr>::parse("1 true IF 2 ELSE false FI +").unwrap();
+    let errs = gsm::typed::check(&script).unwrap_err();
+    assert_eq!(errs.len(), 1);
+    match &errs[0] {
+        TypeError::Mismatch { index: 7, slot: 0, expected: Type::Is(Kind::Num), found: Some(found) } => {
+            assert!(found.contains(&Kind::Num) && found.contains(&Kind::Bool));
+        },
+        e => panic!("{:?}", e)
+    }
+
+    let script = Script::<Instr>::parse("true IF 1 ELSE 2 3 FI").unwrap();
+    assert_eq!(gsm::typed::check(&script).unwrap_err(), vec![TypeError::Flow(FlowError::Height { index: 7, expected: 2, found: 1 })]);
+}
+
+fn run(script: Script<Instr>) -> Stack<Instr> {
+    let mut machine = Machine::from(script);
+    machine.execute(&NullIO).unwrap()
+}
+
+#[test]
+fn optimize_constants() {
+    let script = Script::<Instr>::parse("1 2 + 3 + 0 + true IF 4 + ELSE 100 FI").unwrap();
+    let optimized = Optimizer::standard().optimize(&script);
+    assert_eq!(optimized.to_string(), "10");
+    assert_eq!(run(optimized).pop(), run(script).pop());
+
+    let script = Script::<Instr>::parse("5 false IF 4 + FI 1 +").unwrap();
+    let optimized = Optimizer::standard().optimize(&script);
+    assert_eq!(optimized.to_string(), "6");
+    assert_eq!(run(optimized).pop(), run(script).pop());
+
+    // nested blocks in the arm that can't run go with it
+    let script = Script::<Instr>::parse("1 false IF 2 ELSE 3 true IF 4 + FI FI +").unwrap();
+    let optimized = Optimizer::standard().optimize(&script);
+    assert_eq!(optimized.to_string(), "8");
+    assert_eq!(run(optimized).pop(), run(script).pop());
+}
+
+#[test]
+fn optimize_keeps_entries() {
+    // a label could be looked up and jumped to so the arm it is in stays
+    let script = Script::<Instr>::parse("1 2 + true IF 3 ELSE :x 4 FI 0 +").unwrap();
+    let optimized = Optimizer::standard().optimize(&script);
+    assert_eq!(optimized.to_string(), "3 true IF 3 ELSE :x 4 FI");
+    assert_eq!(optimized.label("x"), Some(5));
+
+    // with no passes nothing changes
+    let optimized = Optimizer::new().optimize(&script);
+    assert_eq!(optimized, script);
+}
+
+#[test]
+fn disassembly() {
+    let mut script = Script::<Instr>::parse("1 true IF 2 ELSE false IF 3 FI 4 FI +").unwrap();
+    script.add_label("sum", 11).unwrap();
+    let listing = gsm::Disassembly::new(&script).to_string();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines.len(), 13);
+    assert_eq!(lines[2], " 2  IF                       # branch 5");
+    assert_eq!(lines[4], " 4  ELSE                     # jump 11 (:sum)");
+    assert_eq!(lines[7], " 7          3");
+    assert_eq!(lines[11], "    :sum");
+    assert_eq!(lines[12], "11  +");
+}
+
+#[test]
+fn control_flow_graph() {
+    let mut script = Script::<Instr>::parse("1 true IF 2 ELSE false IF 3 FI 4 FI +").unwrap();
+    script.add_label("sum", 11).unwrap();
+    let cfg = gsm::Cfg::new(&script);
+    let starts: Vec<usize> = cfg.blocks().iter().map(|b| b.start).collect();
+    assert_eq!(starts, vec![0, 3, 5, 7, 9, 11]);
+    assert_eq!(cfg.block_at(8), Some(3));
+
+    let dot = cfg.to_dot();
+    assert!(dot.starts_with("digraph script {\n"));
+    assert!(dot.contains("    b5 [label=\":sum\\l11: +\\l\"];\n"));
+    assert!(dot.contains("    b0 -> b2 [label=\"branch\"];\n"));
+    assert!(dot.contains("    b1 -> b5 [label=\"jump\"];\n"));
+    assert!(dot.contains("    b5 -> end;\n"));
+}
@@ -0,0 +1,227 @@
+extern crate gsm;
+mod common;
+
+use common::{
+    Instr,
+    Kind,
+    NullIO
+};
+use gsm::{
+    BlockError,
+    EffectError,
+    FlowError,
+    Machine,
+    Optimizer,
+    Script,
+    Type,
+    TypeError,
+    Verifier,
+    VerifyError
+};
+use std::rc::Rc;
+
+fn script() -> Script<Instr> {
+    Script::from(vec![
+        Instr::Num(2),
+        Instr::Call(4),
+        Instr::Call(4),
+        Instr::Halt,
+        Instr::Num(3),
+        Instr::Add,
+        Instr::Ret
+    ])
+}
+
+#[test]
+fn verify_good_script() {
+    let script = script();
+    Verifier::new().verify(&script).unwrap();
+
+    let mut machine = Machine::from(script);
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.pop(), Some(Instr::Num(8)));
+
+    // jumping to the end of the script is how it finishes
+    let script = Script::from(vec![Instr::Num(0), Instr::Jz(2)]);
+    Verifier::new().verify(&script).unwrap();
+}
+
+#[test]
+fn verify_targets() {
+    let script = Script::from(vec![Instr::Num(0), Instr::Jz(1), Instr::Jmp(5), Instr::Halt]);
+    let err = Verifier::new().verify(&script).unwrap_err();
+    assert_eq!(err, VerifyError::TargetOutOfRange { index: 2, target: 5 });
+    assert_eq!(err.index(), 2);
+    assert_eq!(err.to_string(), "instruction at 2 goes to 5 outside the script");
+}
+
+#[test]
+fn verify_blocks() {
+    let script = Script::from(vec![Instr::Num(1), Instr::If, Instr::Num(2)]);
+    let err = Verifier::new().verify(&script).unwrap_err();
+    assert_eq!(err, VerifyError::Block(BlockError::Unclosed(1)));
+    assert_eq!(err.index(), 1);
+}
+
+#[test]
+fn verify_limits() {
+    let script = script();
+    let err = Verifier::new().max_len(5).verify(&script).unwrap_err();
+    assert_eq!(err, VerifyError::TooLong { len: 7, max: 5 });
+    assert!(Verifier::new().max_len(7).verify(&script).is_ok());
+
+    // at most one call
+    let err = Verifier::new().max_opcode(0x05, 1).verify(&script).unwrap_err();
+    assert_eq!(err, VerifyError::TooManyOpcodes { index: 2, opcode: 0x05, max: 1 });
+    assert!(Verifier::new().max_opcode(0x05, 2).verify(&script).is_ok());
+}
+
+#[test]
+fn verify_rules() {
+    // numbers must be small and HALT may only come before the subroutines
+    let mut verifier = Verifier::new();
+    verifier
+        .rule(Rc::new(|_ip: usize, i: &Instr, _s: &Script<Instr>| match i {
+            Instr::Num(n) if n.abs() > 100 => Err(format!("{} is too big", n)),
+            _ => Ok(())
+        }))
+        .rule(Rc::new(|ip: usize, i: &Instr, s: &Script<Instr>| match i {
+            Instr::Halt if s.get(ip + 1) != Some(Instr::Num(3)) => Err("HALT in the wrong place".to_string()),
+            _ => Ok(())
+        }));
+    verifier.verify(&script()).unwrap();
+
+    let script = Script::from(vec![Instr::Num(1), Instr::Num(1000), Instr::Add]);
+    let err = verifier.verify(&script).unwrap_err();
+    assert_eq!(err, VerifyError::Rule { index: 1, msg: "1000 is too big".to_string() });
+    assert_eq!(err.to_string(), "instruction at 1: 1000 is too big");
+
+    let script = Script::from(vec![Instr::Halt]);
+    assert_eq!(verifier.verify(&script).unwrap_err().index(), 0);
+}
+
+#[test]
+fn verify_untrusted_bytecode() {
+    // bytecode that loads fine but jumps out of the script
+    let b = Script::from(vec![Instr::Jmp(9)]).to_bytecode();
+    let script: Script<Instr> = Script::from_bytecode(&b).unwrap();
+    let err = Verifier::new().verify(&script).unwrap_err();
+    assert_eq!(err.index(), 0);
+}
+
+#[test]
+fn stack_effects_with_calls() {
+    // the subroutine takes one item and gives one back
+    let report = gsm::effect::analyze(&script()).unwrap();
+    assert_eq!(report.max_depth, 2);
+    assert_eq!(report.depths[3], Some(1));
+    assert_eq!(report.depths[4], None);
+
+    let script = Script::from(vec![Instr::Call(2), Instr::Halt, Instr::Num(3), Instr::Add, Instr::Ret]);
+    let err = gsm::effect::analyze(&script).unwrap_err();
+    assert_eq!(err, EffectError::Underflow { index: 3, depth: 1, takes: 2 });
+
+    let script = Script::from(vec![Instr::Call(0)]);
+    assert_eq!(gsm::effect::analyze(&script).unwrap_err(), EffectError::Recursive(0));
+
+    let script = Script::from(vec![Instr::Num(1), Instr::Ret]);
+    assert_eq!(gsm::effect::analyze(&script).unwrap_err(), EffectError::Return(1));
+}
+
+#[test]
+fn stack_effects_with_loops() {
+    // a loop that leaves the stack as it found it
+    let script = Script::from(vec![Instr::Num(0), Instr::Jz(0)]);
+    let report = gsm::effect::analyze(&script).unwrap();
+    assert_eq!(report.max_depth, 1);
+    assert_eq!(report.depths[2], Some(0));
+
+    // and one that grows it every time around
+    let script = Script::from(vec![Instr::Num(1), Instr::Jmp(0)]);
+    let err = gsm::effect::analyze(&script).unwrap_err();
+    assert_eq!(err, EffectError::Height { index: 0, expected: 0, found: 1 });
+}
+
+#[test]
+fn type_check_calls() {
+    // the subroutine is checked with what each call leaves on the stack
+    let script = Script::from(vec![
+        Instr::Num(1),
+        Instr::Call(7),
+        Instr::If,
+        Instr::Fi,
+        Instr::Num(2),
+        Instr::Call(7),
+        Instr::Halt,
+        Instr::Num(3),
+        Instr::Add,
+        Instr::Ret
+    ]);
+    gsm::typed::check(&script).unwrap();
+
+    // a literal where a sum is wanted
+    let script = Script::from(vec![Instr::Num(1), Instr::If, Instr::Fi]);
+    let errs = gsm::typed::check(&script).unwrap_err();
+    assert_eq!(errs, vec![TypeError::Mismatch { index: 1, slot: 0, expected: Type::Is(Kind::Sum), found: Some(vec![Kind::Literal]) }]);
+
+    let script = Script::from(vec![Instr::Call(0)]);
+    assert_eq!(gsm::typed::check(&script).unwrap_err(), vec![TypeError::Flow(FlowError::Recursive(0))]);
+
+    let script = Script::from(vec![Instr::Ret]);
+    assert_eq!(gsm::typed::check(&script).unwrap_err(), vec![TypeError::Flow(FlowError::Return(0))]);
+}
+
+#[test]
+fn optimize_dead_code() {
+    let script = Script::from(vec![
+        Instr::Num(1),
+        Instr::Jmp(4),
+        Instr::Num(9),
+        Instr::Num(9),
+        Instr::Num(2),
+        Instr::Call(9),
+        Instr::Halt,
+        Instr::Num(7),
+        Instr::Num(7),
+        Instr::Num(3),
+        Instr::Add,
+        Instr::Ret,
+        Instr::Num(8)
+    ]);
+    let optimized = Optimizer::standard().optimize(&script);
+
+    // the jump and call are moved to where their targets ended up
+    assert_eq!(optimized, Script::from(vec![
+        Instr::Num(1),
+        Instr::Jmp(2),
+        Instr::Num(2),
+        Instr::Call(5),
+        Instr::Halt,
+        Instr::Num(3),
+        Instr::Add,
+        Instr::Ret
+    ]));
+
+    let mut machine = Machine::from(script);
+    let expected = machine.execute(&NullIO).unwrap();
+    let mut machine = Machine::from(optimized);
+    let result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.iter().collect::<Vec<_>>(), expected.iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn control_flow_graph_calls() {
+    let script = script();
+    let cfg = gsm::Cfg::new(&script);
+    let edges: Vec<(usize, usize, gsm::EdgeKind)> = cfg.edges().iter().map(|e| (e.from, e.to, e.kind)).collect();
+    assert_eq!(edges, vec![
+        (0, 3, gsm::EdgeKind::Call),
+        (0, 1, gsm::EdgeKind::Return),
+        (1, 3, gsm::EdgeKind::Call),
+        (1, 2, gsm::EdgeKind::Return),
+        (2, 4, gsm::EdgeKind::Halt)
+    ]);
+    let dot = cfg.to_dot();
+    assert!(dot.contains("    b0 -> b1 [style=dashed, label=\"return\"];\n"));
+    assert!(dot.contains("    b2 -> end [label=\"halt\"];\n"));
+}
@@ -1,10 +1,14 @@
 extern crate gsm;
 use gsm::{
     AppIO,
+    Cause,
     Instruction,
     Machine,
     MachineBuilder,
-    Script
+    MachineError,
+    MachineSnapshot,
+    Script,
+    Step
 };
 use semver::{
     Version,
@@ -13,7 +17,9 @@ use semver::{
 use serde::{
     de,
     Deserialize,
-    Deserializer
+    Deserializer,
+    Serialize,
+    Serializer
 };
 use std::{
     fmt,
@@ -62,6 +68,12 @@ impl fmt::Display for Instr {
     }
 }
 
+impl Serialize for Instr {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
 struct NullIO;
 
 impl AppIO<Instr> for NullIO {
@@ -74,14 +86,14 @@ impl AppIO<Instr> for NullIO {
 
 impl Instruction<Instr> for Instr {
 
-    fn execute(&self, ip: usize, m: &mut Machine<Instr>, _io: &dyn AppIO<Instr>) {
+    fn execute(&self, ip: usize, m: &mut Machine<Instr>, _io: &dyn AppIO<Instr>) -> Result<(), MachineError<Instr>> {
         match self {
             Instr::Version => {
                 if let Some(Instr::Text(s)) = m.pop() {
                     if let Ok(v) = Version::parse(&s) {
                         m.push(Instr::Boolean(m.version_check(&v)));
                         m.pushr(ip + 1);
-                        return;
+                        return Ok(());
                     }
                 }
             }
@@ -90,10 +102,10 @@ impl Instruction<Instr> for Instr {
                 // push the value onto the stack and keep going
                 m.push(self.clone());
                 m.pushr(ip + 1);
-                return;
+                return Ok(());
             }
         }
-        panic!();
+        Err(Cause::TypeMismatch.into())
     }
 }
 
@@ -111,11 +123,11 @@ fn version_0() {
     let mut result = machine.execute(&NullIO).unwrap();
 
     // there should only be one item on the stack
-    assert_eq!(result.size(), 1 as usize);
+    assert_eq!(result.size(), 1);
 
     // the item on the stack should be a Boolean with value "true"
     match result.pop() {
-        Some(Instr::Boolean(b)) => assert_eq!(b, true),
+        Some(Instr::Boolean(b)) => assert!(b),
         _ => panic!()
     }
 }
@@ -133,11 +145,96 @@ fn version_1() {
     let mut result = machine.execute(&NullIO).unwrap();
 
     // there should only be one item on the stack
-    assert_eq!(result.size(), 1 as usize);
+    assert_eq!(result.size(), 1);
 
     // the item on the stack should be a Boolean with value "true"
     match result.pop() {
-        Some(Instr::Boolean(b)) => assert_eq!(b, false),
+        Some(Instr::Boolean(b)) => assert!(!b),
         _ => panic!()
     }
 }
+
+#[test]
+fn snapshot_json() {
+    let script = Script::from(vec![
+        Instr::Text("1.0.0".to_string()),
+        Instr::Version
+    ]);
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .version_req(&VersionReq::parse(">= 1.0.0").unwrap())
+        .build();
+
+    // pause the machine after the first instruction
+    assert!(matches!(machine.step(&NullIO).unwrap(), Step::Running));
+    let json = machine.snapshot().to_json().unwrap();
+    assert_eq!(json, r#"{"version_req":">=1.0.0","stack":["1.0.0"],"rstack":[1],"script":"1.0.0 VERSION","consumed":1}"#);
+
+    // resume it and the version requirement still applies
+    let snap = MachineSnapshot::<Instr>::from_json(&json).unwrap();
+    let mut machine = Machine::from_snapshot(snap, &MachineBuilder::new()).unwrap();
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.size(), 1);
+    assert_eq!(result.pop(), Some(Instr::Boolean(true)));
+}
+
+#[test]
+fn snapshot_cbor() {
+    let script = Script::from(vec![
+        Instr::Text("0.1.0".to_string()),
+        Instr::Version,
+        Instr::Text("1.2.3".to_string()),
+        Instr::Version
+    ]);
+    let mut machine = MachineBuilder::new()
+        .script(&script)
+        .version_req(&VersionReq::parse(">= 1.0.0").unwrap())
+        .build();
+    for _ in 0..3 {
+        machine.step(&NullIO).unwrap();
+    }
+    let cbor = machine.snapshot().to_cbor().unwrap();
+
+    let snap = MachineSnapshot::<Instr>::from_cbor(&cbor).unwrap();
+    assert_eq!(snap.version_req, VersionReq::parse(">= 1.0.0").unwrap());
+    assert_eq!(snap.script, script);
+    assert_eq!(snap.stack.size(), 2);
+    assert_eq!(snap.rstack.top(), Some(&3));
+
+    let mut machine = Machine::from_snapshot(snap, &MachineBuilder::new()).unwrap();
+    let mut result = machine.execute(&NullIO).unwrap();
+    assert_eq!(result.pop(), Some(Instr::Boolean(true)));
+    assert_eq!(result.pop(), Some(Instr::Boolean(false)));
+}
+
+#[test]
+fn snapshot_builder() {
+    let script = Script::from(vec![
+        Instr::Text("a".to_string()),
+        Instr::Text("b".to_string()),
+        Instr::Text("c".to_string())
+    ]);
+    let mut machine = MachineBuilder::new().script(&script).build();
+    machine.step(&NullIO).unwrap();
+    machine.step(&NullIO).unwrap();
+    let snap = machine.snapshot();
+    assert_eq!(snap.consumed, 2);
+
+    // the restored stacks have to fit in the builder's limits
+    let e = Machine::from_snapshot(snap.clone(), MachineBuilder::new().max_stack(1)).err().unwrap();
+    assert!(matches!(e.cause, Cause::StackOverflow));
+    let e = Machine::from_snapshot(snap.clone(), MachineBuilder::new().max_return_stack(0)).err().unwrap();
+    assert!(matches!(e.cause, Cause::ReturnStackOverflow));
+
+    // and the fuel used before the snapshot counts against the builder's
+    let mut machine = Machine::from_snapshot(snap, MachineBuilder::new().fuel(2).max_stack(2)).unwrap();
+    assert_eq!(machine.fuel_remaining(), Some(0));
+    let e = machine.execute(&NullIO).unwrap_err();
+    assert!(matches!(e.cause, Cause::OutOfGas { consumed: 2, .. }));
+}
+
+#[test]
+fn snapshot_bad_version_req() {
+    let json = r#"{"version_req":"not a version","stack":[],"rstack":[0],"script":""}"#;
+    assert!(MachineSnapshot::<Instr>::from_json(json).is_err());
+}